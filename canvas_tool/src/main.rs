@@ -0,0 +1,734 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::{BufReader, BufWriter, Read},
+    num::{NonZeroU32, NonZeroU64},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use msrf::error::IoError;
+use msrf_canvas_base::{
+    CanvasMeta, CanvasRecord, Identifier, MetaIdIndex,
+    analytics::{self, AuthorStats},
+    anonymise::{self, Anonymiser},
+    codec::{
+        self, V0_0, V0_1,
+        index::TimeIndex,
+        stream::{RecordReader, RecordWriter},
+    },
+    heatmap::{self, ColorRamp, HeatmapOptions},
+    history::HistoryIndex,
+    keyframe::Keyframes,
+    position::Position,
+    render::{self, RenderOptions},
+    state,
+    validate::{Severity, Validator},
+};
+
+#[derive(Debug)]
+enum Error {
+    Io(std::io::Error),
+    Codec(codec::Error),
+    Render(render::Error),
+    Anonymise(anonymise::Error),
+    State(state::Error),
+    Analytics(analytics::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Codec(e) => write!(f, "{e}"),
+            Error::Render(e) => write!(f, "{e}"),
+            Error::Anonymise(e) => write!(f, "{e}"),
+            Error::State(e) => write!(f, "{e}"),
+            Error::Analytics(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<IoError<codec::Error>> for Error {
+    fn from(value: IoError<codec::Error>) -> Self {
+        match value {
+            IoError::Io(e) => Error::Io(e),
+            IoError::Parse(e) => Error::Codec(e),
+        }
+    }
+}
+
+impl From<render::Error> for Error {
+    fn from(value: render::Error) -> Self {
+        Error::Render(value)
+    }
+}
+
+impl From<anonymise::Error> for Error {
+    fn from(value: anonymise::Error) -> Self {
+        Error::Anonymise(value)
+    }
+}
+
+impl From<state::Error> for Error {
+    fn from(value: state::Error) -> Self {
+        Error::State(value)
+    }
+}
+
+impl From<analytics::Error> for Error {
+    fn from(value: analytics::Error) -> Self {
+        Error::Analytics(value)
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Inspect, check and convert canvas archives")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the canvas meta, record counts per type and time range
+    Info { archive: PathBuf },
+    /// List every record with its index and byte offset
+    Dump {
+        archive: PathBuf,
+        /// Stop after this many records
+        #[arg(long)]
+        limit: Option<u64>,
+        /// Start at the first record at or after this time
+        #[arg(long)]
+        from: Option<u64>,
+        /// Time index to seek with, built by `index`, instead of indexing the archive first
+        #[arg(long, requires = "from")]
+        index: Option<PathBuf>,
+    },
+    /// Write a time index of an archive for seeking by time
+    Index {
+        archive: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Width of each time bucket in milliseconds
+        #[arg(long, default_value_t = NonZeroU64::new(60_000).unwrap())]
+        bucket: NonZeroU64,
+    },
+    /// List every placement that wrote a pixel
+    History {
+        archive: PathBuf,
+        x: u32,
+        y: u32,
+        /// History index to query, written by `--save`, instead of indexing the archive
+        #[arg(long, conflicts_with = "save")]
+        index: Option<PathBuf>,
+        /// Also write the history index of the archive here
+        #[arg(long)]
+        save: Option<PathBuf>,
+    },
+    /// Print placed and surviving pixels and median survival of every author
+    Authors {
+        archive: PathBuf,
+        /// Analyse the canvas as it was at this time instead of at the end
+        #[arg(long)]
+        time: Option<u64>,
+        /// Only print the authors with the most surviving pixels
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Report inconsistencies, failing if any are errors
+    Validate { archive: PathBuf },
+    /// Render the canvas to a PNG
+    Render {
+        archive: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Render the canvas as it was at this time instead of at the end
+        #[arg(long)]
+        time: Option<u64>,
+        /// Size in pixels of each canvas pixel
+        #[arg(long, default_value_t = NonZeroU32::MIN)]
+        scale: NonZeroU32,
+        /// Color of empty pixels as RRGGBB or RRGGBBAA
+        #[arg(long, value_parser = parse_color, default_value = "00000000")]
+        background: [u8; 4],
+    },
+    /// Render the number of placements of every pixel to a PNG
+    Heatmap {
+        archive: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Only count placements at or after this time
+        #[arg(long, default_value_t = 0)]
+        from: u64,
+        /// Only count placements at or before this time
+        #[arg(long, default_value_t = u64::MAX)]
+        to: u64,
+        #[arg(long, value_enum, default_value_t = HeatmapScale::Linear)]
+        scale: HeatmapScale,
+        /// Comma separated RRGGBB or RRGGBBAA colors from the least to the most placed pixel
+        #[arg(long, value_parser = parse_ramp)]
+        ramp: Option<ColorRamp>,
+        /// Size in pixels of each canvas pixel
+        #[arg(long, default_value_t = NonZeroU32::MIN)]
+        pixel_scale: NonZeroU32,
+        /// Color of pixels that were never placed as RRGGBB or RRGGBBAA
+        #[arg(long, value_parser = parse_color, default_value = "000000")]
+        background: [u8; 4],
+    },
+    /// Re-encode an archive with another codec version
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// Codec version to write, as MAJOR.MINOR
+        #[arg(long, value_parser = parse_version, default_value = "0.0")]
+        codec: u16,
+        /// Insert a keyframe every this many milliseconds
+        #[arg(long)]
+        keyframe_interval: Option<NonZeroU64>,
+    },
+    /// Rewrite identifiers so the archive cannot be tied back to users
+    ///
+    /// Only the archive is rewritten. Identifier table sidecars of the input still hold the
+    /// original identifiers, so discard them and regenerate them from the output.
+    Anonymise {
+        input: PathBuf,
+        output: PathBuf,
+        #[command(subcommand)]
+        mode: AnonymiseMode,
+    },
+}
+
+// Alias so that clap parses the key as one value rather than a list of bytes
+type Key = Vec<u8>;
+
+#[derive(Clone, PartialEq, Subcommand)]
+enum AnonymiseMode {
+    /// Remove identifiers
+    Drop,
+    /// Replace identifiers with their keyed hash
+    Hash {
+        /// Hex encoded key of at least 16 bytes, use a new random key for every release
+        #[arg(long, value_parser = parse_key)]
+        key: Key,
+    },
+    /// Replace identifiers with sequential numbers
+    Sequential,
+}
+
+impl std::fmt::Debug for AnonymiseMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the key
+        match self {
+            AnonymiseMode::Drop => write!(f, "Drop"),
+            AnonymiseMode::Hash { .. } => f.debug_struct("Hash").finish_non_exhaustive(),
+            AnonymiseMode::Sequential => write!(f, "Sequential"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+enum HeatmapScale {
+    /// Proportional to the count
+    Linear,
+    /// Proportional to the logarithm of the count
+    Log,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<ExitCode, Error> {
+    match command {
+        Command::Info { archive } => info(&archive)?,
+        Command::Dump {
+            archive,
+            limit,
+            from,
+            index,
+        } => dump(&archive, limit, from, index.as_deref())?,
+        Command::Index {
+            archive,
+            output,
+            bucket,
+        } => index(&archive, &output, bucket)?,
+        Command::History {
+            archive,
+            x,
+            y,
+            index,
+            save,
+        } => history(
+            &archive,
+            Position::new(x, y),
+            index.as_deref(),
+            save.as_deref(),
+        )?,
+        Command::Authors {
+            archive,
+            time,
+            limit,
+        } => authors(&archive, time.unwrap_or(u64::MAX), limit)?,
+        Command::Validate { archive } => return validate(&archive),
+        Command::Render {
+            archive,
+            output,
+            time,
+            scale,
+            background,
+        } => render(
+            &archive,
+            &output,
+            time.unwrap_or(u64::MAX),
+            &RenderOptions { background, scale },
+        )?,
+        Command::Heatmap {
+            archive,
+            output,
+            from,
+            to,
+            scale,
+            ramp,
+            pixel_scale,
+            background,
+        } => {
+            let scale = match scale {
+                HeatmapScale::Linear => heatmap::Scale::Linear,
+                HeatmapScale::Log => heatmap::Scale::Log,
+            };
+            let options = HeatmapOptions {
+                scale,
+                ramp: ramp.unwrap_or_default(),
+                background,
+                pixel_scale,
+            };
+            heatmap(&archive, &output, from..=to, &options)?
+        }
+        Command::Convert {
+            input,
+            output,
+            codec,
+            keyframe_interval,
+        } => convert(&input, &output, codec, keyframe_interval)?,
+        Command::Anonymise {
+            input,
+            output,
+            mode,
+        } => {
+            let mode = match mode {
+                AnonymiseMode::Drop => anonymise::Mode::Drop,
+                AnonymiseMode::Hash { key } => anonymise::Mode::Hash { key },
+                AnonymiseMode::Sequential => anonymise::Mode::Sequential,
+            };
+            anonymise(&input, &output, mode)?
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn open(path: &Path) -> Result<RecordReader<BufReader<File>>, Error> {
+    Ok(RecordReader::new(BufReader::new(File::open(path)?))?)
+}
+
+/// Run `f` over the records of `reader` up to the first unreadable one, which is reported once
+/// `f` has stopped.
+fn replay<R: Read, T, E: Into<Error>>(
+    reader: RecordReader<R>,
+    f: impl FnOnce(&mut dyn Iterator<Item = CanvasRecord>) -> Result<T, E>,
+) -> Result<T, Error> {
+    let mut error = None;
+    let mut records = reader.map_while(|record| record.map_err(|e| error = Some(e)).ok());
+    let value = f(&mut records).map_err(Into::into)?;
+    match error {
+        Some(e) => Err(e.into()),
+        None => Ok(value),
+    }
+}
+
+fn info(path: &Path) -> Result<(), Error> {
+    let reader = open(path)?;
+    let version = reader.version();
+
+    let mut meta: Option<CanvasMeta> = None;
+    let mut counts: BTreeMap<u16, u64> = BTreeMap::new();
+    let mut range: Option<(u64, u64)> = None;
+    for record in reader {
+        let record = record?;
+        *counts.entry(record.raw_id()).or_default() += 1;
+        for time in record.times() {
+            range = Some(range.map_or((time, time), |(min, max)| (min.min(time), max.max(time))));
+        }
+        if let (None, CanvasRecord::CanvasMeta(rec)) = (&meta, record) {
+            meta = Some(rec);
+        }
+    }
+
+    println!("codec:    {}.{}", version >> 8, version & 0xFF);
+    match meta {
+        Some(meta) => {
+            println!("name:     {}", meta.name);
+            println!("platform: {}", meta.platform);
+            println!("start:    {}", meta.time);
+            println!("size:     {}x{}", meta.size.0, meta.size.1);
+        }
+        None => println!("meta:     missing"),
+    }
+    match range {
+        Some((min, max)) => println!("time:     {min}..={max}"),
+        None => println!("time:     none"),
+    }
+
+    println!("records:  {}", counts.values().sum::<u64>());
+    for (id, count) in counts {
+        println!("  {id:#06x} {:<28} {count}", type_name(id));
+    }
+
+    Ok(())
+}
+
+fn dump(
+    path: &Path,
+    limit: Option<u64>,
+    from: Option<u64>,
+    index: Option<&Path>,
+) -> Result<(), Error> {
+    let mut reader = open(path)?;
+    if let Some(time) = from {
+        let index = match index {
+            Some(index) => TimeIndex::read(BufReader::new(File::open(index)?))?,
+            None => TimeIndex::build(open(path)?, NonZeroU64::new(60_000).unwrap())?,
+        };
+        reader.seek_to_time(&index, time)?;
+    }
+
+    let start = reader.index();
+    while limit.is_none_or(|limit| reader.index() - start < limit) {
+        let (index, offset) = (reader.index(), reader.offset());
+        let Some(record) = reader.read_record()? else {
+            break;
+        };
+
+        println!("{index:>10} @{offset:<12} {record:?}");
+    }
+
+    Ok(())
+}
+
+fn index(path: &Path, output: &Path, bucket: NonZeroU64) -> Result<(), Error> {
+    let index = TimeIndex::build(open(path)?, bucket)?;
+    index.write(BufWriter::new(File::create(output)?))?;
+    println!("{} entries", index.entries().len());
+    Ok(())
+}
+
+fn history(
+    path: &Path,
+    pos: Position,
+    index: Option<&Path>,
+    save: Option<&Path>,
+) -> Result<(), Error> {
+    let history = match index {
+        Some(index) => HistoryIndex::read(BufReader::new(File::open(index)?))?,
+        None => {
+            let mut history = HistoryIndex::new();
+            for record in open(path)? {
+                history.push(&record?)?;
+            }
+            history
+        }
+    };
+    if let Some(save) = save {
+        history.write(BufWriter::new(File::create(save)?))?;
+    }
+
+    for placement in history.pixel(pos) {
+        let col = placement
+            .col
+            .map_or_else(|| "removed".to_string(), |col| col.to_string());
+        println!("{:>10} t={:<14} {col}", placement.index, placement.time);
+    }
+
+    Ok(())
+}
+
+fn authors(path: &Path, time: u64, limit: Option<usize>) -> Result<(), Error> {
+    let analytics = replay(open(path)?, |records| analytics::analyse_at(records, time))?;
+
+    let mut authors: Vec<(String, &AuthorStats)> = analytics
+        .authors()
+        .map(|(id, stats)| (identifier_name(id), stats))
+        .collect();
+    authors.sort_by(|a, b| {
+        b.1.surviving
+            .cmp(&a.1.surviving)
+            .then(b.1.placed.cmp(&a.1.placed))
+    });
+    authors.truncate(limit.unwrap_or(usize::MAX));
+
+    let unattributed = analytics.stats(MetaIdIndex::NONE);
+    let unattributed = unattributed.filter(|stats| stats.placed > 0);
+    println!(
+        "{:<40} {:>10} {:>10} {:>14}",
+        "author", "placed", "surviving", "median"
+    );
+    for (name, stats) in authors
+        .into_iter()
+        .chain(unattributed.map(|stats| ("(none)".to_string(), stats)))
+    {
+        let median = stats
+            .median_survival()
+            .map_or_else(|| "-".to_string(), |median| median.to_string());
+        println!(
+            "{name:<40} {:>10} {:>10} {median:>14}",
+            stats.placed, stats.surviving
+        );
+    }
+
+    Ok(())
+}
+
+fn validate(path: &Path) -> Result<ExitCode, Error> {
+    let mut validator = Validator::new();
+    for record in open(path)? {
+        validator.push(&record?);
+    }
+
+    let diagnostics = validator.finish();
+    for diagnostic in &diagnostics {
+        println!("{diagnostic}");
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    println!(
+        "{} issues ({errors} errors, {} warnings)",
+        diagnostics.len(),
+        diagnostics.len() - errors
+    );
+
+    Ok(if errors == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn render(path: &Path, output: &Path, time: u64, options: &RenderOptions) -> Result<(), Error> {
+    let image = replay(open(path)?, |records| {
+        render::render_at(records, time, options)
+    })?;
+
+    image.write_png(BufWriter::new(File::create(output)?))?;
+    Ok(())
+}
+
+fn heatmap(
+    path: &Path,
+    output: &Path,
+    window: RangeInclusive<u64>,
+    options: &HeatmapOptions,
+) -> Result<(), Error> {
+    let heatmap = replay(open(path)?, |records| heatmap::heatmap(records, window))?;
+
+    heatmap
+        .image(options)?
+        .write_png(BufWriter::new(File::create(output)?))?;
+    Ok(())
+}
+
+fn convert(
+    input: &Path,
+    output: &Path,
+    version: u16,
+    keyframe_interval: Option<NonZeroU64>,
+) -> Result<(), Error> {
+    let reader = open(input)?;
+    let mut writer = RecordWriter::new(BufWriter::new(File::create(output)?), version)?;
+    match keyframe_interval {
+        Some(interval) => replay(reader, |records| {
+            for record in Keyframes::new(records, interval) {
+                writer.write_record(&record?)?;
+            }
+            Ok::<_, Error>(())
+        })?,
+        None => {
+            for record in reader {
+                writer.write_record(&record?)?;
+            }
+        }
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+fn anonymise(input: &Path, output: &Path, mode: anonymise::Mode) -> Result<(), Error> {
+    let reader = open(input)?;
+    let file = BufWriter::new(File::create(output)?);
+    let mut writer = RecordWriter::new(file, reader.version())?;
+    let mut anonymiser = Anonymiser::new(mode)?;
+    for record in reader {
+        if let Some(record) = anonymiser.apply(record?)? {
+            writer.write_record(&record)?;
+        }
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+fn identifier_name(id: &Identifier) -> String {
+    match id {
+        Identifier::Numerical(n) => n.to_string(),
+        Identifier::String(s) => s.clone(),
+        Identifier::Secret(raw) => raw.iter().map(|b| format!("{b:02x}")).collect(),
+    }
+}
+
+fn type_name(id: u16) -> &'static str {
+    use msrf_canvas_base::*;
+
+    match id {
+        CANVAS_META_TYPE_ID => "CanvasMeta",
+        CANVAS_RESIZE_TYPE_ID => "CanvasResize",
+        CANVAS_KEYFRAME_TYPE_ID => "CanvasKeyframe",
+        PALETTE_INSERT_TYPE_ID => "PaletteInsert",
+        PALETTE_REMOVE_TYPE_ID => "PaletteRemove",
+        PLACEMENT_INSERT_TYPE_ID => "PlacementInsert",
+        PLACEMENT_INSERT_SILENT_TYPE_ID => "PlacementInsertQuiet",
+        PLACEMENT_INSERT_FILL_TYPE_ID => "PlacementInsertFill",
+        PLACEMENT_INSERT_FILL_SILENT_TYPE_ID => "PlacementInsertFillQuiet",
+        PLACEMENT_REMOVE_TYPE_ID => "PlacementRemove",
+        PLACEMENT_REMOVE_SILENT_TYPE_ID => "PlacementRemoveQuiet",
+        PLACEMENT_REMOVE_FILL_TYPE_ID => "PlacementRemoveFill",
+        PLACEMENT_REMOVE_FILL_SILENT_TYPE_ID => "PlacementRemoveFillQuiet",
+        PLACEMENT_INSERT_BATCH_TYPE_ID => "PlacementInsertBatch",
+        PLACEMENT_INSERT_BATCH_SILENT_TYPE_ID => "PlacementInsertBatchQuiet",
+        PLACEMENT_REMOVE_BATCH_TYPE_ID => "PlacementRemoveBatch",
+        PLACEMENT_REMOVE_BATCH_SILENT_TYPE_ID => "PlacementRemoveBatchQuiet",
+        IDENTIFIER_NUMERIC_TYPE_ID => "IdentifierNumeric",
+        IDENTIFIER_STRING_TYPE_ID => "IdentifierString",
+        IDENTIFIER_SECRET_TYPE_ID => "IdentifierSecret",
+        _ => "unknown",
+    }
+}
+
+fn parse_color(s: &str) -> Result<[u8; 4], String> {
+    let s = s.strip_prefix('#').unwrap_or(s);
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect::<Option<Vec<u8>>>();
+
+    match bytes.as_deref() {
+        Some(&[r, g, b]) => Ok([r, g, b, 0xFF]),
+        Some(&[r, g, b, a]) => Ok([r, g, b, a]),
+        _ => Err(format!("`{s}` is not a RRGGBB or RRGGBBAA color")),
+    }
+}
+
+fn parse_ramp(s: &str) -> Result<ColorRamp, String> {
+    let stops = s.split(',').map(parse_color).collect::<Result<_, _>>()?;
+    ColorRamp::new(stops).ok_or_else(|| "a ramp needs at least one color".to_string())
+}
+
+fn parse_key(s: &str) -> Result<Vec<u8>, String> {
+    let key = (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect::<Option<Vec<u8>>>();
+
+    match key {
+        Some(key) if !key.is_empty() => Ok(key),
+        _ => Err(format!("`{s}` is not a hex encoded key")),
+    }
+}
+
+fn parse_version(s: &str) -> Result<u16, String> {
+    let version = s
+        .split_once('.')
+        .and_then(|(major, minor)| Some((major.parse::<u8>().ok()?, minor.parse::<u8>().ok()?)))
+        .map(|(major, minor)| (major as u16) << 8 | minor as u16)
+        .ok_or_else(|| format!("`{s}` is not a MAJOR.MINOR version"))?;
+
+    match version {
+        V0_0 | V0_1 => Ok(version),
+        _ => Err(format!("unsupported codec version {s}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cli_parse_color() {
+        assert_eq!(parse_color("ff8000"), Ok([0xFF, 0x80, 0x00, 0xFF]));
+        assert_eq!(parse_color("#10203040"), Ok([0x10, 0x20, 0x30, 0x40]));
+        assert!(parse_color("fff").is_err());
+        assert!(parse_color("gg0000").is_err());
+    }
+
+    #[test]
+    fn cli_parse_ramp() {
+        let ramp = parse_ramp("000000,ffffff80").unwrap();
+        assert_eq!(
+            ramp.stops(),
+            &[[0x00, 0x00, 0x00, 0xFF], [0xFF, 0xFF, 0xFF, 0x80]]
+        );
+        assert!(parse_ramp("000000,").is_err());
+    }
+
+    #[test]
+    fn cli_parse_key() {
+        assert_eq!(parse_key("00ff10"), Ok(vec![0x00, 0xFF, 0x10]));
+        assert!(parse_key("").is_err());
+        assert!(parse_key("abc").is_err());
+    }
+
+    #[test]
+    fn cli_parse_version() {
+        assert_eq!(parse_version("0.0"), Ok(V0_0));
+        assert_eq!(parse_version("0.1"), Ok(V0_1));
+        assert!(parse_version("1.0").is_err());
+        assert!(parse_version("1").is_err());
+    }
+
+    #[test]
+    fn cli_args() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+
+        let key = "00112233445566778899aabbccddeeff";
+        let cli = Cli::try_parse_from(["canvas-tool", "anonymise", "a", "b", "hash", "--key", key]);
+        assert!(matches!(
+            cli.map(|cli| cli.command),
+            Ok(Command::Anonymise {
+                mode: AnonymiseMode::Hash { key },
+                ..
+            }) if key.len() == 16
+        ));
+        assert!(Cli::try_parse_from(["canvas-tool", "anonymise", "a", "b", "hash"]).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use std::{borrow::Borrow, collections::BTreeMap, fmt::Display};
+
+use crate::{
+    CanvasRecord, Identifier, MetaIdIndex,
+    identifier::{self, IdentifierTable},
+    position::Position,
+    render::{Until, record_until},
+    state::{self, CanvasState, change_times},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    State(state::Error),
+    Identifier(identifier::Error),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::State(e) => Some(e),
+            Error::Identifier(e) => Some(e),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::State(e) => write!(f, "cannot replay record: {e}"),
+            Error::Identifier(e) => write!(f, "cannot intern author: {e}"),
+        }
+    }
+}
+
+impl From<state::Error> for Error {
+    fn from(value: state::Error) -> Self {
+        Error::State(value)
+    }
+}
+
+impl From<identifier::Error> for Error {
+    fn from(value: identifier::Error) -> Self {
+        Error::Identifier(value)
+    }
+}
+
+/// Placement currently shown by a pixel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Writer {
+    /// Handle of the author in [`Analytics::table`], [`MetaIdIndex::NONE`] if there was none.
+    pub author: MetaIdIndex,
+    pub time: u64,
+}
+
+/// Totals of the placements of one author.
+///
+/// Every pixel written counts as a placement, so a fill places as many pixels as it covers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthorStats {
+    /// Pixels placed.
+    pub placed: u64,
+    /// Pixels still showing a placement of the author.
+    pub surviving: u64,
+    /// Placements that have ended.
+    pub ended: u64,
+    // Number of ended placements with each survival time
+    survivals: BTreeMap<u64, u64>,
+}
+
+impl AuthorStats {
+    /// Time between a placement and the record that overwrote, removed or discarded it, with the
+    /// number of ended placements that survived for it, from the shortest.
+    pub fn survivals(&self) -> impl Iterator<Item = (u64, u64)> {
+        self.survivals.iter().map(|(&time, &count)| (time, count))
+    }
+
+    /// Lower median of [`survivals`](Self::survivals), or `None` if no placement has ended.
+    pub fn median_survival(&self) -> Option<u64> {
+        let mid = self.ended.checked_sub(1)? / 2;
+        let mut seen = 0;
+        self.survivals().find_map(|(time, count)| {
+            seen += count;
+            (seen > mid).then_some(time)
+        })
+    }
+}
+
+/// Last writer of every pixel and survival of every placement, collected by replaying a stream.
+///
+/// Placements are attributed to the author set by the preceding identifier record. A placement
+/// ends when its pixel is placed again (even with the same colour) or removed, or discarded by a
+/// [`CanvasResize`](crate::CanvasResize), [`CanvasMeta`](crate::CanvasMeta) or a keyframe that
+/// changes it. Placements that have not ended are counted as surviving and their survival so far
+/// is the [`time`](CanvasState::time) of the canvas less their [`Writer::time`].
+#[derive(Debug, Clone)]
+pub struct Analytics {
+    state: CanvasState,
+    table: IdentifierTable,
+    author: MetaIdIndex,
+    writers: Vec<Option<Writer>>,
+    // Indexed by handle, as interned handles are dense
+    authors: Vec<AuthorStats>,
+    unattributed: AuthorStats,
+}
+
+impl Default for Analytics {
+    fn default() -> Self {
+        Self {
+            state: CanvasState::default(),
+            table: IdentifierTable::new(),
+            author: MetaIdIndex::NONE,
+            writers: Vec::new(),
+            authors: Vec::new(),
+            unattributed: AuthorStats::default(),
+        }
+    }
+}
+
+impl Analytics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> &CanvasState {
+        &self.state
+    }
+
+    /// Identifiers of the authors seen so far, in order of first appearance.
+    pub fn table(&self) -> &IdentifierTable {
+        &self.table
+    }
+
+    /// Writer of every pixel in row-major order, `None` if it is empty.
+    pub fn writers(&self) -> &[Option<Writer>] {
+        &self.writers
+    }
+
+    /// Writer of the pixel at `pos`, or `None` if it is empty or outside the canvas.
+    pub fn writer_at(&self, pos: Position) -> Option<&Writer> {
+        let pos = pos.to_index(self.state.size()).ok()?;
+        self.writers[pos as usize].as_ref()
+    }
+
+    /// Totals of every identifier in [`table`](Self::table), in handle order.
+    pub fn authors(&self) -> impl Iterator<Item = (&Identifier, &AuthorStats)> {
+        self.table.iter().zip(&self.authors)
+    }
+
+    /// Totals of an author, [`MetaIdIndex::NONE`] for placements without one.
+    pub fn stats(&self, author: MetaIdIndex) -> Option<&AuthorStats> {
+        if author.is_none() {
+            Some(&self.unattributed)
+        } else if author.is_unique() {
+            None
+        } else {
+            self.authors.get(author.into_index())
+        }
+    }
+
+    /// Apply a record. On error nothing is changed.
+    pub fn push(&mut self, record: &CanvasRecord) -> Result<(), Error> {
+        // Interning may fail, so do it before anything else changes
+        let author = match record.identifier() {
+            Some(id) => Some(self.table.intern(&id)?),
+            None => None,
+        };
+
+        let (previous_time, previous_size) = (self.state.time(), self.state.size());
+        let previous =
+            matches!(record, CanvasRecord::CanvasKeyframe(_)).then(|| self.state.pixels().to_vec());
+        let changes = self.state.apply(record)?;
+
+        if let Some(author) = author {
+            self.author = author;
+            if self.authors.len() < self.table.len() {
+                self.authors.push(AuthorStats::default());
+            }
+        }
+
+        match record {
+            CanvasRecord::CanvasMeta(_) => {
+                self.end_all(previous_time);
+                self.author = MetaIdIndex::NONE;
+            }
+            CanvasRecord::CanvasResize(rec) => {
+                let writers = std::mem::take(&mut self.writers);
+                self.writers = vec![None; self.state.len() as usize];
+                for (pos, writer) in writers.into_iter().enumerate() {
+                    let Some(writer) = writer else {
+                        continue;
+                    };
+                    match rec.remap_index(pos as u64, previous_size) {
+                        Some(pos) => self.writers[pos as usize] = Some(writer),
+                        None => self.end(writer, rec.time),
+                    }
+                }
+            }
+            CanvasRecord::CanvasKeyframe(rec) => {
+                if rec.size != previous_size {
+                    self.end_all(rec.time);
+                } else {
+                    let previous = previous.unwrap_or_default();
+                    for (pos, pixel) in rec.pixels.iter().enumerate() {
+                        if previous[pos] != *pixel
+                            && let Some(writer) = self.writers[pos].take()
+                        {
+                            self.end(writer, rec.time);
+                        }
+                    }
+                }
+            }
+            _ => {
+                for (change, time) in changes.pixels.into_iter().zip(change_times(record)) {
+                    let time = time.unwrap_or(self.state.time());
+                    if let Some(writer) = self.writers[change.pos as usize].take() {
+                        self.end(writer, time);
+                    }
+                    if change.current.is_some() {
+                        let stats = self.stats_mut(self.author);
+                        stats.placed += 1;
+                        stats.surviving += 1;
+                        self.writers[change.pos as usize] = Some(Writer {
+                            author: self.author,
+                            time,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self, writer: Writer, time: u64) {
+        let stats = self.stats_mut(writer.author);
+        // Every writer was counted as surviving when placed
+        debug_assert!(
+            stats.surviving > 0,
+            "ended a placement that was not surviving"
+        );
+        stats.surviving = stats.surviving.saturating_sub(1);
+        stats.ended += 1;
+        *stats
+            .survivals
+            .entry(time.saturating_sub(writer.time))
+            .or_default() += 1;
+    }
+
+    // End every placement and start an empty raster of the current size
+    fn end_all(&mut self, time: u64) {
+        let writers = std::mem::replace(&mut self.writers, vec![None; self.state.len() as usize]);
+        for writer in writers.into_iter().flatten() {
+            self.end(writer, time);
+        }
+    }
+
+    fn stats_mut(&mut self, author: MetaIdIndex) -> &mut AuthorStats {
+        if author.is_none() {
+            &mut self.unattributed
+        } else {
+            &mut self.authors[author.into_index()]
+        }
+    }
+}
+
+/// Collect [`Analytics`] for the canvas as it was at `time`.
+///
+/// As with [`render_at`](crate::render::render_at), records are replayed until the first
+/// placement after `time`, so the stream must be sorted by time.
+pub fn analyse_at<R: Borrow<CanvasRecord>>(
+    records: impl IntoIterator<Item = R>,
+    time: u64,
+) -> Result<Analytics, Error> {
+    let mut analytics = Analytics::new();
+    for record in records {
+        let record = record.borrow();
+        match record_until(record, time) {
+            Until::Whole => analytics.push(record)?,
+            Until::Partial(record) => {
+                analytics.push(&record)?;
+                break;
+            }
+            Until::None => break,
+        }
+    }
+
+    Ok(analytics)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CanvasMeta, CanvasResize, PlacementInsert, PlacementInsertFill, PlacementRemove};
+
+    use super::*;
+
+    fn records() -> Vec<CanvasRecord> {
+        let place = |time, pos, col| PlacementInsert { time, pos, col };
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (2, 2),
+            }),
+            CanvasRecord::PlacementInsert(place(1, 0, 0)),
+            CanvasRecord::IdentifierNumeric(7),
+            CanvasRecord::PlacementInsertBatch(vec![place(2, 1, 0), place(4, 0, 1)]),
+            CanvasRecord::IdentifierString("user".to_string()),
+            // (0, 0) to (0, 1)
+            CanvasRecord::PlacementInsertFill(PlacementInsertFill {
+                time: 10,
+                pos: (0, 2),
+                col: 2,
+            }),
+            CanvasRecord::IdentifierNumeric(7),
+            CanvasRecord::PlacementInsert(place(16, 0, 1)),
+            CanvasRecord::PlacementRemove(PlacementRemove { time: 20, pos: 1 }),
+        ]
+    }
+
+    #[test]
+    fn analytics_writers() {
+        let analytics = analyse_at(records(), u64::MAX).unwrap();
+        let seven = analytics
+            .table()
+            .index_of(&Identifier::Numerical(7))
+            .unwrap();
+        let user = analytics
+            .table()
+            .index_of(&Identifier::String("user".to_string()))
+            .unwrap();
+
+        let writers: Vec<Option<(MetaIdIndex, u64)>> = analytics
+            .writers()
+            .iter()
+            .map(|writer| writer.map(|w| (w.author, w.time)))
+            .collect();
+        assert_eq!(
+            writers,
+            vec![Some((seven, 16)), None, Some((user, 10)), None]
+        );
+        assert_eq!(
+            analytics.writer_at(Position::new(0, 1)).map(|w| w.author),
+            Some(user)
+        );
+
+        let totals: Vec<(u64, u64)> = analytics
+            .authors()
+            .map(|(_, stats)| (stats.placed, stats.surviving))
+            .collect();
+        assert_eq!(totals, vec![(3, 1), (2, 1)]);
+        let unattributed = analytics.stats(MetaIdIndex::NONE).unwrap();
+        assert_eq!((unattributed.placed, unattributed.surviving), (1, 0));
+        assert_eq!(unattributed.survivals().collect::<Vec<_>>(), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn analytics_survival() {
+        let analytics = analyse_at(records(), u64::MAX).unwrap();
+        let seven = analytics
+            .table()
+            .index_of(&Identifier::Numerical(7))
+            .unwrap();
+        let user = analytics
+            .table()
+            .index_of(&Identifier::String("user".to_string()))
+            .unwrap();
+
+        // (0, 0) placed at 4 is overwritten at 10 and (1, 0) placed at 2 removed at 20
+        let stats = analytics.stats(seven).unwrap();
+        assert_eq!(stats.survivals().collect::<Vec<_>>(), vec![(6, 1), (18, 1)]);
+        assert_eq!(stats.median_survival(), Some(6));
+        // (0, 0) placed at 10 is overwritten at 16
+        let stats = analytics.stats(user).unwrap();
+        assert_eq!((stats.ended, stats.median_survival()), (1, Some(6)));
+
+        // At an earlier time, split within the batch
+        let analytics = analyse_at(records(), 3).unwrap();
+        let stats = analytics.stats(seven).unwrap();
+        assert_eq!((stats.placed, stats.surviving), (1, 1));
+        assert_eq!(stats.median_survival(), None);
+
+        // Lower median across repeated survival times
+        let mut stats = AuthorStats {
+            ended: 4,
+            survivals: BTreeMap::from([(1, 2), (9, 2)]),
+            ..AuthorStats::default()
+        };
+        assert_eq!(stats.median_survival(), Some(1));
+        stats.ended += 1;
+        stats.survivals.insert(4, 1);
+        assert_eq!(stats.median_survival(), Some(4));
+    }
+
+    #[test]
+    fn analytics_resize() {
+        let mut records = records();
+        records.push(CanvasRecord::CanvasResize(CanvasResize {
+            time: 30,
+            size: (1, 2),
+            offset: (0, 0),
+        }));
+        let analytics = analyse_at(&records, u64::MAX).unwrap();
+        let seven = analytics
+            .table()
+            .index_of(&Identifier::Numerical(7))
+            .unwrap();
+
+        assert_eq!(analytics.writers().len(), 2);
+        assert_eq!(analytics.writers()[0].map(|w| w.author), Some(seven));
+        // (0, 1) is kept, now the second pixel of the narrower canvas
+        assert!(analytics.writers()[1].is_some());
+        assert_eq!(analytics.stats(seven).unwrap().surviving, 1);
+
+        records.push(CanvasRecord::CanvasResize(CanvasResize {
+            time: 40,
+            size: (1, 1),
+            offset: (0, 0),
+        }));
+        let analytics = analyse_at(&records, u64::MAX).unwrap();
+        let user = analytics
+            .table()
+            .index_of(&Identifier::String("user".to_string()))
+            .unwrap();
+        let stats = analytics.stats(user).unwrap();
+        assert_eq!((stats.surviving, stats.ended), (0, 2));
+        assert_eq!(stats.survivals().collect::<Vec<_>>(), vec![(6, 1), (30, 1)]);
+    }
+}
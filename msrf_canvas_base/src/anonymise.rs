@@ -0,0 +1,263 @@
+//! Rewriting of identifier records so an archive can be released without its users.
+//!
+//! Only the records of the stream are rewritten. An [`IdentifierTable`] sidecar written for the
+//! original archive still holds every original identifier, so discard it and regenerate it from
+//! the anonymised records with [`IdentifierTable::from_records`].
+
+use std::fmt::{Debug, Display};
+
+use hmac_sha256::HMAC;
+
+use crate::{
+    CanvasRecord, Identifier,
+    identifier::{self, IdentifierTable},
+};
+
+/// Shortest key accepted by [`Mode::Hash`], in bytes.
+pub const MIN_KEY_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Identifier(identifier::Error),
+    /// A [`Mode::Hash`] key shorter than [`MIN_KEY_LEN`].
+    KeyTooShort(usize),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Identifier(e) => Some(e),
+            Error::KeyTooShort(_) => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Identifier(e) => write!(f, "{e}"),
+            Error::KeyTooShort(len) => write!(
+                f,
+                "key is {len} bytes but must be at least {MIN_KEY_LEN} bytes"
+            ),
+        }
+    }
+}
+
+impl From<identifier::Error> for Error {
+    fn from(value: identifier::Error) -> Self {
+        Error::Identifier(value)
+    }
+}
+
+/// How identifier records are rewritten.
+#[derive(Clone, PartialEq)]
+pub enum Mode {
+    /// Remove identifier records, leaving every placement without an author.
+    Drop,
+    /// Replace every identifier, secrets included, with an [`IdentifierSecret`] holding its
+    /// HMAC-SHA256 under `key`.
+    ///
+    /// Equal identifiers hash equally, so authors stay linked within an archive. Use a new
+    /// random key of at least [`MIN_KEY_LEN`] bytes for each release so authors cannot be linked
+    /// across archives, and discard it afterwards.
+    ///
+    /// [`IdentifierSecret`]: CanvasRecord::IdentifierSecret
+    Hash { key: Vec<u8> },
+    /// Replace identifiers with [`IdentifierNumeric`](CanvasRecord::IdentifierNumeric) ids
+    /// numbered from 0 in order of first appearance.
+    Sequential,
+}
+
+impl Debug for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the key
+        match self {
+            Mode::Drop => write!(f, "Drop"),
+            Mode::Hash { .. } => f.debug_struct("Hash").finish_non_exhaustive(),
+            Mode::Sequential => write!(f, "Sequential"),
+        }
+    }
+}
+
+/// Rewriter of the identifier records in a stream, passing every other record through.
+#[derive(Clone)]
+pub struct Anonymiser {
+    mode: Mode,
+    table: IdentifierTable,
+}
+
+impl Debug for Anonymiser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the original identifiers
+        f.debug_struct("Anonymiser")
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Anonymiser {
+    /// Anonymiser for `mode`, or an error if its key is shorter than [`MIN_KEY_LEN`].
+    pub fn new(mode: Mode) -> Result<Self, Error> {
+        if let Mode::Hash { key } = &mode
+            && key.len() < MIN_KEY_LEN
+        {
+            return Err(Error::KeyTooShort(key.len()));
+        }
+
+        Ok(Self {
+            mode,
+            table: IdentifierTable::new(),
+        })
+    }
+
+    /// Rewrite `record`, or `None` if it is dropped.
+    pub fn apply(&mut self, record: CanvasRecord) -> Result<Option<CanvasRecord>, Error> {
+        let Some(id) = record.identifier() else {
+            return Ok(Some(record));
+        };
+
+        Ok(match &self.mode {
+            Mode::Drop => None,
+            Mode::Hash { key } => Some(CanvasRecord::IdentifierSecret(hash(&id, key).to_vec())),
+            Mode::Sequential => {
+                let index = self.table.intern(&id)?;
+                Some(CanvasRecord::IdentifierNumeric(index.into_index() as u64))
+            }
+        })
+    }
+}
+
+/// Anonymise a stream of records with a single [`Anonymiser`].
+pub fn anonymise(
+    records: impl IntoIterator<Item = CanvasRecord>,
+    mode: Mode,
+) -> Result<impl Iterator<Item = Result<CanvasRecord, Error>>, Error> {
+    let mut anonymiser = Anonymiser::new(mode)?;
+    Ok(records
+        .into_iter()
+        .filter_map(move |record| anonymiser.apply(record).transpose()))
+}
+
+fn hash(id: &Identifier, key: &[u8]) -> [u8; 32] {
+    // Tag the variant so that e.g. `Numerical(1)` and `Secret([1, 0, ..])` hash differently
+    let mut mac = HMAC::new(key);
+    match id {
+        Identifier::Numerical(n) => {
+            mac.update([0]);
+            mac.update(n.to_le_bytes());
+        }
+        Identifier::String(s) => {
+            mac.update([1]);
+            mac.update(s);
+        }
+        Identifier::Secret(raw) => {
+            mac.update([2]);
+            mac.update(raw);
+        }
+    }
+
+    mac.finalize()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::PlacementInsert;
+
+    use super::*;
+
+    fn records() -> Vec<CanvasRecord> {
+        let place = |pos| {
+            CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 0,
+                pos,
+                col: 0,
+            })
+        };
+        vec![
+            CanvasRecord::IdentifierString("alice".to_string()),
+            place(0),
+            CanvasRecord::IdentifierNumeric(1234),
+            place(1),
+            CanvasRecord::IdentifierString("alice".to_string()),
+            place(2),
+            CanvasRecord::IdentifierSecret(vec![0xAB]),
+        ]
+    }
+
+    fn run(mode: Mode) -> Vec<CanvasRecord> {
+        anonymise(records(), mode)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn anonymise_drop_sequential() {
+        let dropped = run(Mode::Drop);
+        assert_eq!(dropped.len(), 3);
+        assert!(dropped.iter().all(|rec| rec.identifier().is_none()));
+
+        let ids: Vec<Option<Identifier>> = run(Mode::Sequential)
+            .iter()
+            .map(CanvasRecord::identifier)
+            .collect();
+        assert_eq!(
+            ids,
+            [Some(0), None, Some(1), None, Some(0), None, Some(2)]
+                .map(|id| id.map(Identifier::Numerical))
+        );
+    }
+
+    #[test]
+    fn anonymise_hash() {
+        let hashed = run(Mode::Hash {
+            key: b"sixteen byte key".to_vec(),
+        });
+        let secret = |i: usize| match &hashed[i] {
+            CanvasRecord::IdentifierSecret(raw) => raw.clone(),
+            rec => panic!("expected secret, got {rec:?}"),
+        };
+
+        assert_eq!(hashed.len(), records().len());
+        assert_eq!(hashed[1], records()[1]);
+        assert_eq!(secret(0).len(), 32);
+        assert_eq!(secret(0), secret(4));
+        assert_ne!(secret(0), secret(2));
+        assert_ne!(secret(6), vec![0xAB]);
+
+        // Re-keying changes every identifier
+        let rekeyed = run(Mode::Hash {
+            key: b"some other key!!".to_vec(),
+        });
+        assert_ne!(rekeyed[0], hashed[0]);
+        assert_ne!(rekeyed[6], hashed[6]);
+    }
+
+    #[test]
+    fn anonymise_key() {
+        let mode = Mode::Hash {
+            key: b"short".to_vec(),
+        };
+        assert_eq!(
+            Anonymiser::new(mode.clone()).map(|_| ()),
+            Err(Error::KeyTooShort(5))
+        );
+        assert_eq!(format!("{mode:?}"), "Hash { .. }");
+
+        let mode = Mode::Hash {
+            key: vec![0x5A; MIN_KEY_LEN],
+        };
+        let anonymiser = Anonymiser::new(mode).unwrap();
+        assert!(!format!("{anonymiser:?}").contains("90"));
+
+        // Nor the identifiers seen so far
+        let mut anonymiser = Anonymiser::new(Mode::Sequential).unwrap();
+        let user = CanvasRecord::IdentifierString("user".to_string());
+        anonymiser.apply(user).unwrap();
+        assert_eq!(
+            format!("{anonymiser:?}"),
+            "Anonymiser { mode: Sequential, .. }"
+        );
+    }
+}
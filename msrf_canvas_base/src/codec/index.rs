@@ -0,0 +1,302 @@
+//! Time index sidecar for seeking an archive by timestamp.
+//!
+//! The index stores [`INDEX_MAGIC`], the bucket width (`u64`) and entry count (`u64`), followed by
+//! every [`Entry`] as its bucket, record index, byte offset and base time (`u64` each). All
+//! integers are little endian.
+
+use std::{
+    io::{Read, Write},
+    num::NonZeroU64,
+};
+
+use msrf::error::IoError;
+
+use crate::CanvasRecord;
+
+use super::{
+    Error,
+    stream::{HEADER_LEN, RecordReader},
+};
+
+pub const INDEX_MAGIC: [u8; 4] = *b"MCTI";
+
+/// Position of a record in an archive, with the codec state needed to resume decoding there.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Index of the record.
+    pub index: u64,
+    /// Byte offset of the record from the start of the archive.
+    pub offset: u64,
+    /// [`Serialiser::time`](super::Serialiser::time) before the record.
+    pub time: u64,
+}
+
+impl Checkpoint {
+    /// The first record of an archive.
+    pub const START: Self = Self {
+        index: 0,
+        offset: HEADER_LEN,
+        time: 0,
+    };
+}
+
+/// Where to start reading for times in a bucket.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Time divided by the bucket width.
+    pub bucket: u64,
+    /// First record after the last timed record of an earlier bucket, so records without a
+    /// time (such as identifiers) directly before the bucket's first timed record are included.
+    pub checkpoint: Checkpoint,
+}
+
+/// Sparse index from time buckets to the [`Checkpoint`] their records start at.
+///
+/// Only buckets holding the time of at least one record have an entry. Records are indexed by
+/// their first time (for batches, that of their first placement) and a record whose bucket is
+/// before that of an earlier record is not indexed, so seeking assumes a stream sorted by time.
+#[derive(Debug, Clone)]
+pub struct TimeIndex {
+    bucket: NonZeroU64,
+    entries: Vec<Entry>,
+    // Start of the run of untimed records before the next record
+    run: Option<Checkpoint>,
+}
+
+impl TimeIndex {
+    pub fn new(bucket: NonZeroU64) -> Self {
+        Self {
+            bucket,
+            entries: Vec::new(),
+            run: None,
+        }
+    }
+
+    /// Index an archive by reading it to the end.
+    pub fn build<R: Read>(
+        mut reader: RecordReader<R>,
+        bucket: NonZeroU64,
+    ) -> Result<Self, IoError<Error>> {
+        let mut index = Self::new(bucket);
+        loop {
+            let checkpoint = reader.checkpoint();
+            let Some(record) = reader.read_record()? else {
+                return Ok(index);
+            };
+            index.push(&record, checkpoint);
+        }
+    }
+
+    /// Width of a bucket in units of time.
+    pub fn bucket(&self) -> NonZeroU64 {
+        self.bucket
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Index `record`, which is stored at `checkpoint`. Records must be pushed in order.
+    pub fn push(&mut self, record: &CanvasRecord, checkpoint: Checkpoint) {
+        let run = *self.run.get_or_insert(checkpoint);
+        let Some(time) = record.time() else {
+            return;
+        };
+
+        let bucket = time / self.bucket.get();
+        if self
+            .entries
+            .last()
+            .is_none_or(|entry| bucket > entry.bucket)
+        {
+            self.entries.push(Entry {
+                bucket,
+                checkpoint: run,
+            });
+        }
+        self.run = None;
+    }
+
+    /// Entry to scan from for the first record at or after `time`.
+    ///
+    /// This is the entry of the last bucket not after that of `time`, as a batch starting in an
+    /// earlier bucket may still hold later times, or the first entry if every bucket is after it.
+    pub fn find(&self, time: u64) -> Option<&Entry> {
+        let bucket = time / self.bucket.get();
+        let i = self.entries.partition_point(|entry| entry.bucket <= bucket);
+        self.entries[..i].last().or(self.entries.first())
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(&INDEX_MAGIC)?;
+        writer.write_all(&self.bucket.get().to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for entry in &self.entries {
+            let Checkpoint {
+                index,
+                offset,
+                time,
+            } = entry.checkpoint;
+            for value in [entry.bucket, index, offset, time] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, IoError<Error>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != INDEX_MAGIC {
+            return Err(IoError::Parse(Error::field("magic", &magic)));
+        }
+
+        let mut read_u64 = || -> std::io::Result<u64> {
+            let mut buf = [0; 8];
+            reader.read_exact(&mut buf)?;
+            Ok(u64::from_le_bytes(buf))
+        };
+
+        let bucket = read_u64()?;
+        let bucket = NonZeroU64::new(bucket)
+            .ok_or_else(|| IoError::Parse(Error::field("bucket", &bucket.to_le_bytes())))?;
+        let len = read_u64()?;
+
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let entry = Entry {
+                bucket: read_u64()?,
+                checkpoint: Checkpoint {
+                    index: read_u64()?,
+                    offset: read_u64()?,
+                    time: read_u64()?,
+                },
+            };
+            if entries
+                .last()
+                .is_some_and(|last: &Entry| entry.bucket <= last.bucket)
+            {
+                return Err(IoError::Parse(Error::field(
+                    "bucket",
+                    &entry.bucket.to_le_bytes(),
+                )));
+            }
+            entries.push(entry);
+        }
+
+        Ok(Self {
+            bucket,
+            entries,
+            run: None,
+        })
+    }
+}
+
+// Latest time of a record, that of the last placement for batches
+pub(super) fn end_time(record: &CanvasRecord) -> Option<u64> {
+    record.times().last()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{
+        CanvasMeta, PlacementInsert,
+        codec::{V0_0, V0_1, stream::RecordWriter},
+    };
+
+    use super::*;
+
+    fn records() -> Vec<CanvasRecord> {
+        let place = |time| PlacementInsert {
+            time,
+            pos: 0,
+            col: 0,
+        };
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 100,
+                size: (4, 4),
+            }),
+            CanvasRecord::IdentifierNumeric(1),
+            CanvasRecord::PlacementInsert(place(105)),
+            CanvasRecord::PlacementInsert(place(112)),
+            CanvasRecord::IdentifierNumeric(2),
+            CanvasRecord::PlacementInsertBatch(vec![place(115), place(131)]),
+            CanvasRecord::IdentifierNumeric(3),
+            CanvasRecord::PlacementInsert(place(150)),
+        ]
+    }
+
+    fn archive(version: u16) -> (Vec<u8>, TimeIndex) {
+        let bucket = NonZeroU64::new(10).unwrap();
+        let mut writer = RecordWriter::new(Vec::new(), version)
+            .unwrap()
+            .with_time_index(bucket);
+        for record in &records() {
+            writer.write_record(record).unwrap();
+        }
+
+        let index = writer.time_index().unwrap().clone();
+        (writer.finish().unwrap(), index)
+    }
+
+    #[test]
+    fn index_entries() {
+        let (buf, index) = archive(V0_1);
+        let entries: Vec<(u64, u64)> = index
+            .entries()
+            .iter()
+            .map(|entry| (entry.bucket, entry.checkpoint.index))
+            .collect();
+        assert_eq!(entries, vec![(10, 0), (11, 3), (15, 6)]);
+        assert_eq!(index.entries()[1].checkpoint.time, 105);
+
+        let reader = RecordReader::new(buf.as_slice()).unwrap();
+        let built = TimeIndex::build(reader, index.bucket()).unwrap();
+        assert_eq!(built.entries(), index.entries());
+
+        let mut sidecar = Vec::new();
+        index.write(&mut sidecar).unwrap();
+        let read = TimeIndex::read(sidecar.as_slice()).unwrap();
+        assert_eq!(read.entries(), index.entries());
+        assert!(TimeIndex::read(&sidecar[1..]).is_err());
+    }
+
+    #[test]
+    fn index_seek() {
+        for version in [V0_0, V0_1] {
+            let (buf, index) = archive(version);
+            let mut reader = RecordReader::new(Cursor::new(buf)).unwrap();
+
+            // (target time, index of the first record read)
+            let expected = [
+                (0, 0),
+                (105, 1),
+                (106, 3),
+                (113, 4),
+                (120, 4),
+                (132, 6),
+                (150, 6),
+            ];
+            for (time, first) in expected {
+                reader.seek_to_time(&index, time).unwrap();
+                assert_eq!(reader.index(), first, "seek to {time}");
+                let record = reader.read_record().unwrap();
+                assert_eq!(record.as_ref(), records().get(first as usize));
+            }
+
+            // Times decode correctly after seeking backwards in a delta codec
+            reader.seek_to_time(&index, 113).unwrap();
+            let rest: Vec<CanvasRecord> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+            assert_eq!(rest, records()[4..]);
+
+            reader.seek_to_time(&index, 151).unwrap();
+            assert_eq!(reader.read_record().unwrap(), None);
+        }
+    }
+}
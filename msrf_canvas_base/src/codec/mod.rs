@@ -1,53 +1,524 @@
 use std::{fmt::Display, io::Write, str::Utf8Error};
 
-use msrf::error::IoError;
+use msrf::{RecordSerialise, error::IoError};
 
-use crate::CanvasMeta;
+use crate::{
+    CanvasKeyframe, CanvasMeta, CanvasRecord, CanvasRecordRef, CanvasResize, PaletteInsert,
+    PaletteRemove, PlacementInsert, PlacementInsertFill, PlacementRemove, PlacementRemoveFill,
+};
 
-mod v0_0;
+pub mod index;
+pub mod stream;
+pub mod v0_0;
+pub mod v0_1;
+
+/// Codec versions are encoded as `major << 8 | minor`.
+pub const V0_0: u16 = 0x0000;
+pub const V0_1: u16 = 0x0001;
 
-// TODO: Deduplicate common record errors
 #[derive(Debug, PartialEq)]
 pub enum Error {
     UnexpectedType(u16),
-    InvalidValueLength,
-    InvalidUTF8(Utf8Error),
-    InvalidField(Vec<u8>)
+    /// A field did not fit in the value (or in the output buffer when serialising).
+    InvalidValueLength {
+        field: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    InvalidUTF8 {
+        field: &'static str,
+        source: Utf8Error,
+    },
+    InvalidField {
+        field: &'static str,
+        value: Vec<u8>,
+    },
+    UnsupportedVersion(u16),
+    /// An error within a record of an archive, see [`Error::in_record`].
+    Record {
+        index: u64,
+        type_id: u16,
+        offset: u64,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Attach the position of the failing record within an archive.
+    ///
+    /// `offset` is the byte offset of the record from the start of the archive.
+    pub fn in_record(self, index: u64, type_id: u16, offset: u64) -> Self {
+        Error::Record {
+            index,
+            type_id,
+            offset,
+            source: Box::new(self),
+        }
+    }
+
+    /// The error without any record context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Record { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+
+    pub(crate) fn field(field: &'static str, value: &[u8]) -> Self {
+        Error::InvalidField {
+            field,
+            value: value.to_vec(),
+        }
+    }
+
+    /// Map the length reported by a failed `insert`/`extract` given `actual` bytes remaining.
+    pub(crate) fn length(field: &'static str, actual: usize) -> impl FnOnce(usize) -> Self {
+        move |expected| Error::InvalidValueLength {
+            field,
+            expected,
+            actual,
+        }
+    }
+
+    pub(crate) fn utf8(field: &'static str) -> impl FnOnce(Utf8Error) -> Self {
+        move |source| Error::InvalidUTF8 { field, source }
+    }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUTF8 { source, .. } => Some(source),
+            Error::Record { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::UnexpectedType(id) => write!(f, "unexpected type {id}"),
-            Error::InvalidValueLength => write!(f, "value too small"),
-            Error::InvalidUTF8(e) => e.fmt(f),
-            Error::InvalidField(culprit) => write!(f, "invalid data in record ({culprit:x?})"),
+            Error::UnexpectedType(id) => write!(f, "unexpected type {id:#06x}"),
+            Error::InvalidValueLength {
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "field `{field}` needs {expected} bytes but only {actual} remain"
+            ),
+            Error::InvalidUTF8 { field, source } => write!(f, "field `{field}`: {source}"),
+            Error::InvalidField { field, value } => {
+                write!(f, "invalid data in field `{field}` ({value:x?})")
+            }
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported codec version {}.{}",
+                version >> 8,
+                version & 0xFF
+            ),
+            Error::Record {
+                index,
+                type_id,
+                offset,
+                source,
+            } => write!(
+                f,
+                "record {index} (type {type_id:#06x}) at byte {offset}: {source}"
+            ),
+        }
+    }
+}
+
+/// Serialiser for any supported codec version, see [`serialiser_for`].
+#[derive(Debug)]
+pub enum Serialiser {
+    V0_0(v0_0::Serialiser),
+    V0_1(v0_1::Serialiser),
+}
+
+impl Serialiser {
+    pub fn version(&self) -> u16 {
+        match self {
+            Serialiser::V0_0(_) => V0_0,
+            Serialiser::V0_1(_) => V0_1,
+        }
+    }
+
+    /// Time that the next record's delta is relative to, always 0 for codecs without deltas.
+    pub fn time(&self) -> u64 {
+        match self {
+            Serialiser::V0_0(_) => 0,
+            Serialiser::V0_1(serialiser) => serialiser.time(),
+        }
+    }
+
+    /// Resume a stream at a record whose delta is relative to `time`, see [`time`](Self::time).
+    pub fn set_time(&self, time: u64) {
+        if let Serialiser::V0_1(serialiser) = self {
+            serialiser.set_time(time);
+        }
+    }
+
+    /// Decode a record borrowing its strings and byte fields from `value`.
+    pub fn deserialise_record_ref<'a>(
+        &self,
+        id: u16,
+        value: &'a [u8],
+    ) -> Result<CanvasRecordRef<'a>, Error> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.deserialise_record_ref(id, value),
+            Serialiser::V0_1(serialiser) => serialiser.deserialise_record_ref(id, value),
+        }
+    }
+
+    /// Exact number of bytes [`RecordSerialise::serialise_record`] would write for `record`.
+    pub fn encoded_len(&self, record: &CanvasRecord) -> Result<usize, Error> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.encoded_len(record),
+            Serialiser::V0_1(serialiser) => serialiser.encoded_len(record),
         }
     }
 }
 
-impl From<usize> for Error {
-    fn from(_value: usize) -> Self {
-        Error::InvalidValueLength
+impl RecordSerialise for Serialiser {
+    type Err = Error;
+
+    type Record = CanvasRecord;
+
+    fn deserialise_record(&self, id: u16, value: &[u8]) -> Result<Self::Record, Self::Err> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.deserialise_record(id, value),
+            Serialiser::V0_1(serialiser) => serialiser.deserialise_record(id, value),
+        }
+    }
+
+    fn serialise_record(
+        &self,
+        value: &mut [u8],
+        record: &Self::Record,
+    ) -> Result<usize, Self::Err> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.serialise_record(value, record),
+            Serialiser::V0_1(serialiser) => serialiser.serialise_record(value, record),
+        }
     }
 }
 
-impl From<Utf8Error> for Error {
-    fn from(value: Utf8Error) -> Self {
-        Error::InvalidUTF8(value)
+/// Create a serialiser for the codec `version` an archive was written with.
+///
+/// Use [`CURRENT_VERSION`](crate::CURRENT_VERSION) when writing new archives.
+pub fn serialiser_for(version: u16) -> Result<Serialiser, Error> {
+    match version {
+        V0_0 => Ok(Serialiser::V0_0(v0_0::Serialiser)),
+        V0_1 => Ok(Serialiser::V0_1(v0_1::Serialiser::new())),
+        _ => Err(Error::UnsupportedVersion(version)),
     }
 }
-// pub trait RawSerialiser {
-//     fn write_source_add<W: Write>(&self, rec: &SourceAdd, wtr: W) -> Result<(), IoError<DesError>>;
-//     fn write_source_remove<W: Write>(
-//         &self,
-//         rec: &SourceRemove,
-//         wtr: W,
-//     ) -> Result<(), IoError<DesError>>;
-// }
 
+/// Streaming writer encoding record values directly into a [`Write`].
+///
+/// Only the record value is written; framing it with a type id and length is left to the
+/// container.
 pub trait RawSerialiser {
-    fn write_canvas_meta<W: Write>(&self, rec: CanvasMeta, wtr: W) -> Result<(), IoError<Error>>;
-}
\ No newline at end of file
+    fn write_canvas_meta<W: Write>(&self, rec: &CanvasMeta, wtr: W) -> Result<(), IoError<Error>>;
+    fn write_canvas_resize<W: Write>(
+        &self,
+        rec: &CanvasResize,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_canvas_keyframe<W: Write>(
+        &self,
+        rec: &CanvasKeyframe,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_palette_insert<W: Write>(
+        &self,
+        rec: &PaletteInsert,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_palette_remove<W: Write>(
+        &self,
+        rec: &PaletteRemove,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_placement_insert<W: Write>(
+        &self,
+        rec: &PlacementInsert,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_placement_insert_fill<W: Write>(
+        &self,
+        rec: &PlacementInsertFill,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_placement_remove<W: Write>(
+        &self,
+        rec: &PlacementRemove,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_placement_remove_fill<W: Write>(
+        &self,
+        rec: &PlacementRemoveFill,
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_placement_insert_batch<W: Write>(
+        &self,
+        rec: &[PlacementInsert],
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_placement_remove_batch<W: Write>(
+        &self,
+        rec: &[PlacementRemove],
+        wtr: W,
+    ) -> Result<(), IoError<Error>>;
+    fn write_identifier_numeric<W: Write>(&self, rec: u64, wtr: W) -> Result<(), IoError<Error>>;
+    fn write_identifier_string<W: Write>(&self, rec: &str, wtr: W) -> Result<(), IoError<Error>>;
+    fn write_identifier_secret<W: Write>(&self, rec: &[u8], wtr: W) -> Result<(), IoError<Error>>;
+
+    /// Write the value of any record, quiet variants share the encoding of their counterpart.
+    fn write_record<W: Write>(&self, rec: &CanvasRecord, wtr: W) -> Result<(), IoError<Error>> {
+        match rec {
+            CanvasRecord::CanvasMeta(rec) => self.write_canvas_meta(rec, wtr),
+            CanvasRecord::CanvasResize(rec) => self.write_canvas_resize(rec, wtr),
+            CanvasRecord::CanvasKeyframe(rec) => self.write_canvas_keyframe(rec, wtr),
+            CanvasRecord::PaletteInsert(rec) => self.write_palette_insert(rec, wtr),
+            CanvasRecord::PaletteRemove(rec) => self.write_palette_remove(rec, wtr),
+            CanvasRecord::PlacementInsert(rec) | CanvasRecord::PlacementInsertQuiet(rec) => {
+                self.write_placement_insert(rec, wtr)
+            }
+            CanvasRecord::PlacementInsertFill(rec)
+            | CanvasRecord::PlacementInsertFillQuiet(rec) => {
+                self.write_placement_insert_fill(rec, wtr)
+            }
+            CanvasRecord::PlacementRemove(rec) | CanvasRecord::PlacementRemoveQuiet(rec) => {
+                self.write_placement_remove(rec, wtr)
+            }
+            CanvasRecord::PlacementRemoveFill(rec)
+            | CanvasRecord::PlacementRemoveFillQuiet(rec) => {
+                self.write_placement_remove_fill(rec, wtr)
+            }
+            CanvasRecord::PlacementInsertBatch(rec)
+            | CanvasRecord::PlacementInsertBatchQuiet(rec) => {
+                self.write_placement_insert_batch(rec, wtr)
+            }
+            CanvasRecord::PlacementRemoveBatch(rec)
+            | CanvasRecord::PlacementRemoveBatchQuiet(rec) => {
+                self.write_placement_remove_batch(rec, wtr)
+            }
+            CanvasRecord::IdentifierNumeric(rec) => self.write_identifier_numeric(*rec, wtr),
+            CanvasRecord::IdentifierString(rec) => self.write_identifier_string(rec, wtr),
+            CanvasRecord::IdentifierSecret(rec) => self.write_identifier_secret(rec, wtr),
+        }
+    }
+}
+
+impl RawSerialiser for Serialiser {
+    fn write_canvas_meta<W: Write>(&self, rec: &CanvasMeta, wtr: W) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_canvas_meta(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_canvas_meta(rec, wtr),
+        }
+    }
+
+    fn write_canvas_resize<W: Write>(
+        &self,
+        rec: &CanvasResize,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_canvas_resize(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_canvas_resize(rec, wtr),
+        }
+    }
+
+    fn write_canvas_keyframe<W: Write>(
+        &self,
+        rec: &CanvasKeyframe,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_canvas_keyframe(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_canvas_keyframe(rec, wtr),
+        }
+    }
+
+    fn write_palette_insert<W: Write>(
+        &self,
+        rec: &PaletteInsert,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_palette_insert(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_palette_insert(rec, wtr),
+        }
+    }
+
+    fn write_palette_remove<W: Write>(
+        &self,
+        rec: &PaletteRemove,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_palette_remove(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_palette_remove(rec, wtr),
+        }
+    }
+
+    fn write_placement_insert<W: Write>(
+        &self,
+        rec: &PlacementInsert,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_placement_insert(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_placement_insert(rec, wtr),
+        }
+    }
+
+    fn write_placement_insert_fill<W: Write>(
+        &self,
+        rec: &PlacementInsertFill,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_placement_insert_fill(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_placement_insert_fill(rec, wtr),
+        }
+    }
+
+    fn write_placement_remove<W: Write>(
+        &self,
+        rec: &PlacementRemove,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_placement_remove(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_placement_remove(rec, wtr),
+        }
+    }
+
+    fn write_placement_remove_fill<W: Write>(
+        &self,
+        rec: &PlacementRemoveFill,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_placement_remove_fill(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_placement_remove_fill(rec, wtr),
+        }
+    }
+
+    fn write_placement_insert_batch<W: Write>(
+        &self,
+        rec: &[PlacementInsert],
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_placement_insert_batch(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_placement_insert_batch(rec, wtr),
+        }
+    }
+
+    fn write_placement_remove_batch<W: Write>(
+        &self,
+        rec: &[PlacementRemove],
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_placement_remove_batch(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_placement_remove_batch(rec, wtr),
+        }
+    }
+
+    fn write_identifier_numeric<W: Write>(&self, rec: u64, wtr: W) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_identifier_numeric(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_identifier_numeric(rec, wtr),
+        }
+    }
+
+    fn write_identifier_string<W: Write>(&self, rec: &str, wtr: W) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_identifier_string(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_identifier_string(rec, wtr),
+        }
+    }
+
+    fn write_identifier_secret<W: Write>(&self, rec: &[u8], wtr: W) -> Result<(), IoError<Error>> {
+        match self {
+            Serialiser::V0_0(serialiser) => serialiser.write_identifier_secret(rec, wtr),
+            Serialiser::V0_1(serialiser) => serialiser.write_identifier_secret(rec, wtr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CURRENT_VERSION, PLACEMENT_INSERT_TYPE_ID, PlacementInsert};
+
+    use super::*;
+
+    #[test]
+    fn serialiser_dispatch() {
+        let record = CanvasRecord::PlacementInsert(PlacementInsert {
+            time: 1234,
+            pos: 21,
+            col: 5,
+        });
+
+        for (version, len) in [(V0_0, 20), (V0_1, 4)] {
+            let serialiser = serialiser_for(version).expect("unsupported version");
+            assert_eq!(serialiser.version(), version);
+
+            assert_eq!(serialiser.encoded_len(&record), Ok(len));
+
+            let mut buf = [0; 32];
+            let written = serialiser
+                .serialise_record(&mut buf, &record)
+                .expect("failed serialise");
+            assert_eq!(written, len);
+
+            let mut wtr = Vec::new();
+            serialiser_for(version)
+                .expect("unsupported version")
+                .write_record(&record, &mut wtr)
+                .expect("failed write");
+            assert_eq!(wtr, &buf[..written]);
+
+            let deserialiser = serialiser_for(version).expect("unsupported version");
+            let decoded = deserialiser
+                .deserialise_record(PLACEMENT_INSERT_TYPE_ID, &buf[..written])
+                .expect("failed deserialise");
+            assert_eq!(decoded, record);
+        }
+
+        assert_eq!(
+            serialiser_for(CURRENT_VERSION).map(|s| s.version()),
+            Ok(CURRENT_VERSION)
+        );
+        assert_eq!(
+            serialiser_for(0x0100).map(|s| s.version()),
+            Err(Error::UnsupportedVersion(0x0100))
+        );
+    }
+
+    #[test]
+    fn error_context() {
+        let err = Error::InvalidValueLength {
+            field: "time",
+            expected: 8,
+            actual: 3,
+        }
+        .in_record(12, crate::PLACEMENT_INSERT_TYPE_ID, 4096);
+
+        assert_eq!(
+            err.to_string(),
+            "record 12 (type 0x0020) at byte 4096: field `time` needs 8 bytes but only 3 remain"
+        );
+        assert!(matches!(
+            err.root(),
+            Error::InvalidValueLength { field: "time", .. }
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+//! Archive framing for a sequence of records.
+//!
+//! An archive starts with [`MAGIC`] and the codec version (`u16`), followed by every record as
+//! its type id (`u16`), value length (`u32`) and value. All integers are little endian.
+
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    num::NonZeroU64,
+};
+
+use msrf::{RecordSerialise, error::IoError};
+
+use crate::CanvasRecord;
+
+use super::{
+    Error, RawSerialiser, Serialiser,
+    index::{Checkpoint, TimeIndex, end_time},
+    serialiser_for,
+};
+
+pub const MAGIC: [u8; 4] = *b"MCNV";
+pub const HEADER_LEN: u64 = 6;
+pub const RECORD_HEADER_LEN: u64 = 6;
+
+/// Reader decoding records from an archive with the serialiser named in its header.
+///
+/// Errors carry the index, type id and byte offset of the failing record.
+#[derive(Debug)]
+pub struct RecordReader<R> {
+    reader: R,
+    serialiser: Serialiser,
+    index: u64,
+    offset: u64,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, IoError<Error>> {
+        let mut header = [0; HEADER_LEN as usize];
+        reader.read_exact(&mut header)?;
+
+        if header[..4] != MAGIC {
+            return Err(IoError::Parse(Error::field("magic", &header[..4])));
+        }
+
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        Ok(Self {
+            reader,
+            serialiser: serialiser_for(version).map_err(IoError::Parse)?,
+            index: 0,
+            offset: HEADER_LEN,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn version(&self) -> u16 {
+        self.serialiser.version()
+    }
+
+    /// Index of the next record.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Byte offset of the next record from the start of the archive.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Position and codec state of the next record.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            index: self.index,
+            offset: self.offset,
+            time: self.serialiser.time(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the next record, or `None` at the end of the archive.
+    pub fn read_record(&mut self) -> Result<Option<CanvasRecord>, IoError<Error>> {
+        let (index, offset) = (self.index, self.offset);
+
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        let read = read_full(&mut self.reader, &mut header)?;
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let type_id = u16::from_le_bytes([header[0], header[1]]);
+        let context = |e: Error| IoError::Parse(e.in_record(index, type_id, offset));
+        if read < header.len() {
+            return Err(context(Error::length("header", read)(header.len())));
+        }
+
+        let len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        self.buf.clear();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut self.buf)?;
+        if self.buf.len() < len {
+            return Err(context(Error::length("value", self.buf.len())(len)));
+        }
+
+        let record = self
+            .serialiser
+            .deserialise_record(type_id, &self.buf)
+            .map_err(context)?;
+
+        self.index += 1;
+        self.offset += RECORD_HEADER_LEN + len as u64;
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read + Seek> RecordReader<R> {
+    /// Continue reading from `checkpoint`, which must be taken from this archive.
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> std::io::Result<()> {
+        self.reader.seek(SeekFrom::Start(checkpoint.offset))?;
+        self.index = checkpoint.index;
+        self.offset = checkpoint.offset;
+        self.serialiser.set_time(checkpoint.time);
+        Ok(())
+    }
+
+    /// Position the reader at the first record with a time at or after `time`, preceded by any
+    /// records without a time (such as identifiers) directly before it.
+    ///
+    /// Only the records of one bucket of `index` are scanned. A batch spanning `time` counts as
+    /// at or after it. If every record is before `time` the reader is left at the end.
+    pub fn seek_to_time(&mut self, index: &TimeIndex, time: u64) -> Result<(), IoError<Error>> {
+        let start = index
+            .find(time)
+            .map_or(Checkpoint::START, |entry| entry.checkpoint);
+        self.restore(start)?;
+
+        let mut start = start;
+        while let Some(record) = self.read_record()? {
+            match end_time(&record) {
+                Some(end) if end < time => start = self.checkpoint(),
+                Some(_) => {
+                    self.restore(start)?;
+                    break;
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<CanvasRecord, IoError<Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Writer framing records into an archive.
+#[derive(Debug)]
+pub struct RecordWriter<W> {
+    writer: W,
+    serialiser: Serialiser,
+    index: u64,
+    offset: u64,
+    buf: Vec<u8>,
+    time_index: Option<TimeIndex>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(mut writer: W, version: u16) -> Result<Self, IoError<Error>> {
+        let serialiser = serialiser_for(version).map_err(IoError::Parse)?;
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&version.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            serialiser,
+            index: 0,
+            offset: HEADER_LEN,
+            buf: Vec::new(),
+            time_index: None,
+        })
+    }
+
+    /// Build a [`TimeIndex`] with buckets of `bucket` time over the records written.
+    pub fn with_time_index(mut self, bucket: NonZeroU64) -> Self {
+        self.time_index = Some(TimeIndex::new(bucket));
+        self
+    }
+
+    pub fn version(&self) -> u16 {
+        self.serialiser.version()
+    }
+
+    /// Index of the next record.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Byte offset of the next record from the start of the archive.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Position and codec state of the next record.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            index: self.index,
+            offset: self.offset,
+            time: self.serialiser.time(),
+        }
+    }
+
+    /// Index of the records written so far, if enabled with
+    /// [`with_time_index`](Self::with_time_index).
+    pub fn time_index(&self) -> Option<&TimeIndex> {
+        self.time_index.as_ref()
+    }
+
+    pub fn write_record(&mut self, record: &CanvasRecord) -> Result<(), IoError<Error>> {
+        let (index, type_id, offset) = (self.index, record.raw_id(), self.offset);
+        let context = |e: Error| IoError::Parse(e.in_record(index, type_id, offset));
+        let checkpoint = self.checkpoint();
+
+        // Written straight into the buffer so keyframes are only compressed once
+        self.buf.clear();
+        self.serialiser
+            .write_record(record, &mut self.buf)
+            .map_err(|e| match e {
+                IoError::Parse(e) => context(e),
+                e => e,
+            })?;
+        let len = self.buf.len();
+        let len_field = u32::try_from(len)
+            .map_err(|_| context(Error::field("length", &(len as u64).to_le_bytes())))?;
+
+        self.writer.write_all(&type_id.to_le_bytes())?;
+        self.writer.write_all(&len_field.to_le_bytes())?;
+        self.writer.write_all(&self.buf)?;
+        if let Some(time_index) = &mut self.time_index {
+            time_index.push(record, checkpoint);
+        }
+
+        self.index += 1;
+        self.offset += RECORD_HEADER_LEN + len as u64;
+        Ok(())
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, IoError<Error>> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// Like `read_exact`, but reports how much was read instead of failing at the end of input.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        CanvasMeta, PLACEMENT_INSERT_TYPE_ID, PlacementInsert,
+        codec::{V0_0, V0_1},
+    };
+
+    use super::*;
+
+    fn records() -> Vec<CanvasRecord> {
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 1000,
+                size: (16, 16),
+            }),
+            CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 1010,
+                pos: 3,
+                col: 1,
+            }),
+            CanvasRecord::IdentifierString("user".to_string()),
+            CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 1005,
+                pos: 4,
+                col: 2,
+            }),
+        ]
+    }
+
+    #[test]
+    fn stream_roundtrip() {
+        for version in [V0_0, V0_1] {
+            let mut writer = RecordWriter::new(Vec::new(), version).unwrap();
+            for record in &records() {
+                writer.write_record(record).unwrap();
+            }
+            let offset = writer.offset();
+            let buf = writer.finish().unwrap();
+
+            assert_eq!(buf.len() as u64, offset);
+
+            let reader = RecordReader::new(buf.as_slice()).unwrap();
+            assert_eq!(reader.version(), version);
+
+            let decoded: Vec<CanvasRecord> = reader.collect::<Result<_, _>>().unwrap();
+            assert_eq!(decoded, records());
+        }
+    }
+
+    #[test]
+    fn stream_truncated() {
+        let mut writer = RecordWriter::new(Vec::new(), V0_0).unwrap();
+        for record in &records()[..2] {
+            writer.write_record(record).unwrap();
+        }
+        let mut buf = writer.finish().unwrap();
+        buf.truncate(buf.len() - 3);
+
+        let mut reader = RecordReader::new(buf.as_slice()).unwrap();
+        assert!(reader.read_record().unwrap().is_some());
+        let offset = reader.offset();
+
+        let err = reader.read_record().expect_err("read truncated record");
+        assert!(matches!(
+            err,
+            IoError::Parse(Error::Record { index: 1, type_id: PLACEMENT_INSERT_TYPE_ID, offset: o, .. })
+                if o == offset
+        ));
+    }
+
+    #[test]
+    fn stream_bad_header() {
+        let err = RecordReader::new(b"NOPE\0\0".as_slice()).expect_err("read bad magic");
+        assert!(matches!(
+            err,
+            IoError::Parse(Error::InvalidField { field: "magic", .. })
+        ));
+
+        let err = RecordReader::new(b"MCNV\xFF\xFF".as_slice()).expect_err("read bad version");
+        assert!(matches!(
+            err,
+            IoError::Parse(Error::UnsupportedVersion(0xFFFF))
+        ));
+    }
+}
@@ -1,57 +1,147 @@
-use std::num::NonZeroU32;
+use std::{io::Write, num::NonZeroU32};
 
-use super::Error;
+use msrf::error::IoError;
+
+use super::{Error, RawSerialiser};
 use crate::{
-    CanvasMeta, CanvasRecord, PaletteInsert, PaletteRemove, PlacementInsert, PlacementInsertFill,
-    PlacementRemove, PlacementRemoveFill,
+    CanvasKeyframe, CanvasMeta, CanvasMetaRef, CanvasRecord, CanvasRecordRef, CanvasResize,
+    PaletteInsert, PaletteInsertRef, PaletteRemove, PlacementInsert, PlacementInsertFill,
+    PlacementRemove, PlacementRemoveFill, position,
 };
 
-pub struct Serialiser;
+// Fixed encoded sizes of records
+const CANVAS_RESIZE_LEN: usize = 24;
+const PLACEMENT_INSERT_LEN: usize = 20;
+const PLACEMENT_INSERT_FILL_LEN: usize = 28;
+const PLACEMENT_REMOVE_LEN: usize = 16;
+const PLACEMENT_REMOVE_FILL_LEN: usize = 24;
 
-// TODO: Multiple insert, remove, etc
-impl RecordSerialise for Serialiser {
-    type Err = Error;
+const KEYFRAME_COMPRESSION_LEVEL: u8 = 6;
 
-    type Record = CanvasRecord;
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Serialiser;
 
-    fn deserialise_record(&self, id: u16, value: &[u8]) -> Result<Self::Record, Self::Err> {
+impl Serialiser {
+    /// Decode a record borrowing its strings and byte fields from `value`.
+    pub fn deserialise_record_ref<'a>(
+        &self,
+        id: u16,
+        value: &'a [u8],
+    ) -> Result<CanvasRecordRef<'a>, Error> {
         match id {
-            crate::CANVAS_META_TYPE_ID => des_canvas_meta(value).map(Self::Record::from),
-            crate::PALETTE_INSERT_TYPE_ID => des_palette_insert(value).map(Self::Record::from),
-            crate::PALETTE_REMOVE_TYPE_ID => des_palette_remove(value).map(Self::Record::from),
-            crate::PLACEMENT_INSERT_TYPE_ID => des_placement_insert(value).map(Self::Record::from),
+            crate::CANVAS_META_TYPE_ID => des_canvas_meta(value).map(CanvasRecordRef::CanvasMeta),
+            crate::CANVAS_RESIZE_TYPE_ID => {
+                des_canvas_resize(value).map(CanvasRecordRef::CanvasResize)
+            }
+            crate::CANVAS_KEYFRAME_TYPE_ID => {
+                des_canvas_keyframe(value).map(CanvasRecordRef::CanvasKeyframe)
+            }
+            crate::PALETTE_INSERT_TYPE_ID => {
+                des_palette_insert(value).map(CanvasRecordRef::PaletteInsert)
+            }
+            crate::PALETTE_REMOVE_TYPE_ID => {
+                des_palette_remove(value).map(CanvasRecordRef::PaletteRemove)
+            }
+            crate::PLACEMENT_INSERT_TYPE_ID => {
+                des_placement_insert(value).map(CanvasRecordRef::PlacementInsert)
+            }
             crate::PLACEMENT_INSERT_SILENT_TYPE_ID => {
-                des_placement_insert(value).map(CanvasRecord::PlacementInsertQuiet)
+                des_placement_insert(value).map(CanvasRecordRef::PlacementInsertQuiet)
             }
             crate::PLACEMENT_INSERT_FILL_TYPE_ID => {
-                des_placement_insert_fill(value).map(Self::Record::from)
+                des_placement_insert_fill(value).map(CanvasRecordRef::PlacementInsertFill)
             }
             crate::PLACEMENT_INSERT_FILL_SILENT_TYPE_ID => {
-                des_placement_insert_fill(value).map(CanvasRecord::PlacementInsertFillQuiet)
+                des_placement_insert_fill(value).map(CanvasRecordRef::PlacementInsertFillQuiet)
+            }
+            crate::PLACEMENT_REMOVE_TYPE_ID => {
+                des_placement_remove(value).map(CanvasRecordRef::PlacementRemove)
             }
-            crate::PLACEMENT_REMOVE_TYPE_ID => des_placement_remove(value).map(Self::Record::from),
             crate::PLACEMENT_REMOVE_SILENT_TYPE_ID => {
-                des_placement_remove(value).map(CanvasRecord::PlacementRemoveQuiet)
+                des_placement_remove(value).map(CanvasRecordRef::PlacementRemoveQuiet)
             }
             crate::PLACEMENT_REMOVE_FILL_TYPE_ID => {
-                des_placement_remove_fill(value).map(Self::Record::from)
+                des_placement_remove_fill(value).map(CanvasRecordRef::PlacementRemoveFill)
             }
             crate::PLACEMENT_REMOVE_FILL_SILENT_TYPE_ID => {
-                des_placement_remove_fill(value).map(CanvasRecord::PlacementRemoveFillQuiet)
+                des_placement_remove_fill(value).map(CanvasRecordRef::PlacementRemoveFillQuiet)
+            }
+            crate::PLACEMENT_INSERT_BATCH_TYPE_ID => {
+                des_placement_insert_batch(value).map(CanvasRecordRef::PlacementInsertBatch)
+            }
+            crate::PLACEMENT_INSERT_BATCH_SILENT_TYPE_ID => {
+                des_placement_insert_batch(value).map(CanvasRecordRef::PlacementInsertBatchQuiet)
+            }
+            crate::PLACEMENT_REMOVE_BATCH_TYPE_ID => {
+                des_placement_remove_batch(value).map(CanvasRecordRef::PlacementRemoveBatch)
+            }
+            crate::PLACEMENT_REMOVE_BATCH_SILENT_TYPE_ID => {
+                des_placement_remove_batch(value).map(CanvasRecordRef::PlacementRemoveBatchQuiet)
             }
             crate::IDENTIFIER_NUMERIC_TYPE_ID => {
-                des_identify_numeric(value).map(CanvasRecord::IdentifierNumeric)
+                des_identify_numeric(value).map(CanvasRecordRef::IdentifierNumeric)
             }
             crate::IDENTIFIER_STRING_TYPE_ID => {
-                des_identify_string(value).map(CanvasRecord::IdentifierString)
+                des_identify_string(value).map(CanvasRecordRef::IdentifierString)
             }
             crate::IDENTIFIER_SECRET_TYPE_ID => {
-                des_identify_secret(value).map(CanvasRecord::IdentifierSecret)
+                des_identify_secret(value).map(CanvasRecordRef::IdentifierSecret)
             }
-            _ => return Err(Error::UnexpectedType(id)),
+            _ => Err(Error::UnexpectedType(id)),
         }
     }
 
+    /// Exact number of bytes [`RecordSerialise::serialise_record`] writes for `record`.
+    ///
+    /// Records that fail to serialise (e.g. oversized `CanvasMeta` strings) still report the
+    /// size they would have occupied. Keyframes are compressed to measure them, so this is an
+    /// error if their pixels are invalid.
+    pub fn encoded_len(&self, record: &CanvasRecord) -> Result<usize, Error> {
+        Ok(match record {
+            CanvasRecord::CanvasMeta(rec) => 1 + rec.name.len() + 1 + rec.platform.len() + 16,
+            CanvasRecord::CanvasResize(_) => CANVAS_RESIZE_LEN,
+            CanvasRecord::CanvasKeyframe(rec) => 16 + deflate_pixels(&rec.pixels)?.len(),
+            CanvasRecord::PaletteInsert(rec) => 4 + rec.colors.len() * 4,
+            CanvasRecord::PaletteRemove(rec) => {
+                if rec.length.get() > 1 {
+                    8
+                } else {
+                    4
+                }
+            }
+            CanvasRecord::PlacementInsert(_) | CanvasRecord::PlacementInsertQuiet(_) => {
+                PLACEMENT_INSERT_LEN
+            }
+            CanvasRecord::PlacementInsertFill(_) | CanvasRecord::PlacementInsertFillQuiet(_) => {
+                PLACEMENT_INSERT_FILL_LEN
+            }
+            CanvasRecord::PlacementRemove(_) | CanvasRecord::PlacementRemoveQuiet(_) => {
+                PLACEMENT_REMOVE_LEN
+            }
+            CanvasRecord::PlacementRemoveFill(_) | CanvasRecord::PlacementRemoveFillQuiet(_) => {
+                PLACEMENT_REMOVE_FILL_LEN
+            }
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => batch.len() * PLACEMENT_INSERT_LEN,
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => batch.len() * PLACEMENT_REMOVE_LEN,
+            CanvasRecord::IdentifierNumeric(_) => 8,
+            CanvasRecord::IdentifierString(s) => s.len(),
+            CanvasRecord::IdentifierSecret(raw) => raw.len(),
+        })
+    }
+}
+
+impl RecordSerialise for Serialiser {
+    type Err = Error;
+
+    type Record = CanvasRecord;
+
+    fn deserialise_record(&self, id: u16, value: &[u8]) -> Result<Self::Record, Self::Err> {
+        self.deserialise_record_ref(id, value)
+            .map(CanvasRecordRef::into_owned)
+    }
+
     fn serialise_record(
         &self,
         value: &mut [u8],
@@ -59,6 +149,10 @@ impl RecordSerialise for Serialiser {
     ) -> Result<usize, Self::Err> {
         match record {
             CanvasRecord::CanvasMeta(canvas_meta) => ser_canvas_meta(value, canvas_meta),
+            CanvasRecord::CanvasResize(canvas_resize) => ser_canvas_resize(value, canvas_resize),
+            CanvasRecord::CanvasKeyframe(canvas_keyframe) => {
+                ser_canvas_keyframe(value, canvas_keyframe)
+            }
             CanvasRecord::PaletteInsert(palette_insert) => {
                 ser_palette_insert(value, palette_insert)
             }
@@ -81,6 +175,14 @@ impl RecordSerialise for Serialiser {
             | CanvasRecord::PlacementRemoveFillQuiet(placement_remove_fill) => {
                 ser_placement_remove_fill(value, placement_remove_fill)
             }
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => {
+                ser_placement_insert_batch(value, batch)
+            }
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => {
+                ser_placement_remove_batch(value, batch)
+            }
             CanvasRecord::IdentifierNumeric(n) => ser_identify_numeric(value, *n),
             CanvasRecord::IdentifierString(s) => ser_identify_string(value, s),
             CanvasRecord::IdentifierSecret(raw) => ser_identify_secret(value, raw),
@@ -88,44 +190,232 @@ impl RecordSerialise for Serialiser {
     }
 }
 
-fn ser_canvas_meta(buf: &mut [u8], record: &CanvasMeta) -> Result<usize, Error> {
+impl RawSerialiser for Serialiser {
+    fn write_canvas_meta<W: Write>(
+        &self,
+        rec: &CanvasMeta,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        let name_len = short_str_len("name", &rec.name).map_err(IoError::Parse)?;
+        let platform_len = short_str_len("platform", &rec.platform).map_err(IoError::Parse)?;
+
+        wtr.write_all(&[name_len])?;
+        wtr.write_all(rec.name.as_bytes())?;
+        wtr.write_all(&[platform_len])?;
+        wtr.write_all(rec.platform.as_bytes())?;
+        wtr.write_all(&rec.time.to_le_bytes())?;
+        wtr.write_all(&rec.size.0.to_le_bytes())?;
+        wtr.write_all(&rec.size.1.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_canvas_resize<W: Write>(
+        &self,
+        rec: &CanvasResize,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.time.to_le_bytes())?;
+        wtr.write_all(&rec.size.0.to_le_bytes())?;
+        wtr.write_all(&rec.size.1.to_le_bytes())?;
+        wtr.write_all(&rec.offset.0.to_le_bytes())?;
+        wtr.write_all(&rec.offset.1.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_canvas_keyframe<W: Write>(
+        &self,
+        rec: &CanvasKeyframe,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        let data = deflate_pixels(&rec.pixels).map_err(IoError::Parse)?;
+        wtr.write_all(&rec.time.to_le_bytes())?;
+        wtr.write_all(&rec.size.0.to_le_bytes())?;
+        wtr.write_all(&rec.size.1.to_le_bytes())?;
+        wtr.write_all(&data)?;
+
+        Ok(())
+    }
+
+    fn write_palette_insert<W: Write>(
+        &self,
+        rec: &PaletteInsert,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.offset.to_le_bytes())?;
+        wtr.write_all(rec.colors.as_flattened())?;
+
+        Ok(())
+    }
+
+    fn write_palette_remove<W: Write>(
+        &self,
+        rec: &PaletteRemove,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.offset.to_le_bytes())?;
+        if rec.length.get() > 1 {
+            wtr.write_all(&rec.length.get().to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn write_placement_insert<W: Write>(
+        &self,
+        rec: &PlacementInsert,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.time.to_le_bytes())?;
+        wtr.write_all(&rec.pos.to_le_bytes())?;
+        wtr.write_all(&rec.col.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_placement_insert_fill<W: Write>(
+        &self,
+        rec: &PlacementInsertFill,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.time.to_le_bytes())?;
+        wtr.write_all(&rec.pos.0.to_le_bytes())?;
+        wtr.write_all(&rec.pos.1.to_le_bytes())?;
+        wtr.write_all(&rec.col.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_placement_remove<W: Write>(
+        &self,
+        rec: &PlacementRemove,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.time.to_le_bytes())?;
+        wtr.write_all(&rec.pos.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_placement_remove_fill<W: Write>(
+        &self,
+        rec: &PlacementRemoveFill,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.time.to_le_bytes())?;
+        wtr.write_all(&rec.pos.0.to_le_bytes())?;
+        wtr.write_all(&rec.pos.1.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_placement_insert_batch<W: Write>(
+        &self,
+        rec: &[PlacementInsert],
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        rec.iter()
+            .try_for_each(|rec| self.write_placement_insert(rec, &mut wtr))
+    }
+
+    fn write_placement_remove_batch<W: Write>(
+        &self,
+        rec: &[PlacementRemove],
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        rec.iter()
+            .try_for_each(|rec| self.write_placement_remove(rec, &mut wtr))
+    }
+
+    fn write_identifier_numeric<W: Write>(
+        &self,
+        rec: u64,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(&rec.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_identifier_string<W: Write>(
+        &self,
+        rec: &str,
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(rec.as_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_identifier_secret<W: Write>(
+        &self,
+        rec: &[u8],
+        mut wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        wtr.write_all(rec)?;
+
+        Ok(())
+    }
+}
+
+// Length prefix of the strings in `CanvasMeta`
+fn short_str_len(field: &'static str, s: &str) -> Result<u8, Error> {
+    s.len()
+        .try_into()
+        .map_err(|_| Error::field(field, &s.len().to_le_bytes()))
+}
+
+pub(super) fn ser_canvas_meta(buf: &mut [u8], record: &CanvasMeta) -> Result<usize, Error> {
     let len = buf.len();
     let mut buf = buf;
 
-    let name_len = record.name.len();
-    buf.insert_u8(
-        name_len
-            .try_into()
-            .map_err(|_| Error::InvalidField(name_len.to_le_bytes().to_vec()))?,
-    )?;
-    buf.insert(record.name.as_bytes())?;
-
-    let platform_name_len = record.platform.len();
-    buf.insert_u8(
-        platform_name_len
-            .try_into()
-            .map_err(|_| Error::InvalidField(platform_name_len.to_le_bytes().to_vec()))?,
-    )?;
-
-    buf.insert(record.platform.as_bytes())?;
-    buf.insert_u64(record.time)?;
-    buf.insert_u32(record.size.0)?;
-    buf.insert_u32(record.size.1)?;
+    buf.insert_u8(short_str_len("name", &record.name)?)
+        .map_err(Error::length("name_len", buf.len()))?;
+    buf.insert(record.name.as_bytes())
+        .map_err(Error::length("name", buf.len()))?;
+    buf.insert_u8(short_str_len("platform", &record.platform)?)
+        .map_err(Error::length("platform_len", buf.len()))?;
+    buf.insert(record.platform.as_bytes())
+        .map_err(Error::length("platform", buf.len()))?;
+    buf.insert_u64(record.time)
+        .map_err(Error::length("time", buf.len()))?;
+    buf.insert_u32(record.size.0)
+        .map_err(Error::length("size.0", buf.len()))?;
+    buf.insert_u32(record.size.1)
+        .map_err(Error::length("size.1", buf.len()))?;
 
     Ok(len - buf.len())
 }
 
-fn des_canvas_meta(buf: &[u8]) -> Result<CanvasMeta, Error> {
+pub(super) fn des_canvas_meta(buf: &[u8]) -> Result<CanvasMetaRef<'_>, Error> {
     let mut buf = buf;
 
-    let name_len = buf.extract_u8()? as usize;
-    let name = str::from_utf8(buf.extract(name_len)?)?.to_string();
-    let platform_len = buf.extract_u8()? as usize;
-    let platform = str::from_utf8(buf.extract(platform_len)?)?.to_string();
-    let time = buf.extract_u64()?;
-    let size = (buf.extract_u32()?, buf.extract_u32()?);
-
-    Ok(CanvasMeta {
+    let name_len = buf
+        .extract_u8()
+        .map_err(Error::length("name_len", buf.len()))? as usize;
+    let name = buf
+        .extract(name_len)
+        .map_err(Error::length("name", buf.len()))?;
+    let name = str::from_utf8(name).map_err(Error::utf8("name"))?;
+    let platform_len = buf
+        .extract_u8()
+        .map_err(Error::length("platform_len", buf.len()))? as usize;
+    let platform = buf
+        .extract(platform_len)
+        .map_err(Error::length("platform", buf.len()))?;
+    let platform = str::from_utf8(platform).map_err(Error::utf8("platform"))?;
+    let time = buf
+        .extract_u64()
+        .map_err(Error::length("time", buf.len()))?;
+    let size = (
+        buf.extract_u32()
+            .map_err(Error::length("size.0", buf.len()))?,
+        buf.extract_u32()
+            .map_err(Error::length("size.1", buf.len()))?,
+    );
+
+    Ok(CanvasMetaRef {
         name,
         platform,
         time,
@@ -133,38 +423,161 @@ fn des_canvas_meta(buf: &[u8]) -> Result<CanvasMeta, Error> {
     })
 }
 
-fn ser_palette_insert(buf: &mut [u8], record: &PaletteInsert) -> Result<usize, Error> {
+fn ser_canvas_resize(buf: &mut [u8], record: &CanvasResize) -> Result<usize, Error> {
     let len = buf.len();
     let mut buf = buf;
 
-    buf.insert_u32(record.offset)?;
-    for color in &record.colors {
-        buf.insert(color)?;
+    buf.insert_u64(record.time)
+        .map_err(Error::length("time", buf.len()))?;
+    buf.insert_u32(record.size.0)
+        .map_err(Error::length("size.0", buf.len()))?;
+    buf.insert_u32(record.size.1)
+        .map_err(Error::length("size.1", buf.len()))?;
+    buf.insert_u32(record.offset.0)
+        .map_err(Error::length("offset.0", buf.len()))?;
+    buf.insert_u32(record.offset.1)
+        .map_err(Error::length("offset.1", buf.len()))?;
+
+    Ok(len - buf.len())
+}
+
+fn des_canvas_resize(buf: &[u8]) -> Result<CanvasResize, Error> {
+    let mut buf = buf;
+
+    let time = buf
+        .extract_u64()
+        .map_err(Error::length("time", buf.len()))?;
+    let size = (
+        buf.extract_u32()
+            .map_err(Error::length("size.0", buf.len()))?,
+        buf.extract_u32()
+            .map_err(Error::length("size.1", buf.len()))?,
+    );
+    let offset = (
+        buf.extract_u32()
+            .map_err(Error::length("offset.0", buf.len()))?,
+        buf.extract_u32()
+            .map_err(Error::length("offset.1", buf.len()))?,
+    );
+
+    Ok(CanvasResize { time, size, offset })
+}
+
+// Keyframe pixels are deflated as `u32` palette index + 1 each, with 0 for an empty pixel
+pub(super) fn deflate_pixels(pixels: &[Option<u32>]) -> Result<Vec<u8>, Error> {
+    let mut raw = Vec::with_capacity(pixels.len() * 4);
+    for pixel in pixels {
+        let value = match pixel {
+            Some(col) => col
+                .checked_add(1)
+                .ok_or_else(|| Error::field("pixels", &col.to_le_bytes()))?,
+            None => 0,
+        };
+        raw.extend_from_slice(&value.to_le_bytes());
     }
 
+    Ok(miniz_oxide::deflate::compress_to_vec(
+        &raw,
+        KEYFRAME_COMPRESSION_LEVEL,
+    ))
+}
+
+pub(super) fn inflate_pixels(buf: &[u8], size: (u32, u32)) -> Result<Vec<Option<u32>>, Error> {
+    let len = position::pixel_count(size)
+        .ok()
+        .and_then(|count| count.checked_mul(4))
+        .ok_or_else(|| {
+            let mut value = size.0.to_le_bytes().to_vec();
+            value.extend_from_slice(&size.1.to_le_bytes());
+            Error::field("size", &value)
+        })?;
+    let invalid = || Error::field("pixels", &buf[..buf.len().min(16)]);
+    let raw =
+        miniz_oxide::inflate::decompress_to_vec_with_limit(buf, len).map_err(|_| invalid())?;
+    if raw.len() != len {
+        return Err(invalid());
+    }
+
+    Ok(raw
+        .chunks_exact(4)
+        .map(|value| u32::from_le_bytes([value[0], value[1], value[2], value[3]]).checked_sub(1))
+        .collect())
+}
+
+fn ser_canvas_keyframe(buf: &mut [u8], record: &CanvasKeyframe) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    let data = deflate_pixels(&record.pixels)?;
+    buf.insert_u64(record.time)
+        .map_err(Error::length("time", buf.len()))?;
+    buf.insert_u32(record.size.0)
+        .map_err(Error::length("size.0", buf.len()))?;
+    buf.insert_u32(record.size.1)
+        .map_err(Error::length("size.1", buf.len()))?;
+    buf.insert(&data)
+        .map_err(Error::length("pixels", buf.len()))?;
+
     Ok(len - buf.len())
 }
 
-fn des_palette_insert(buf: &[u8]) -> Result<PaletteInsert, Error> {
+fn des_canvas_keyframe(buf: &[u8]) -> Result<CanvasKeyframe, Error> {
+    let mut buf = buf;
+
+    let time = buf
+        .extract_u64()
+        .map_err(Error::length("time", buf.len()))?;
+    let size = (
+        buf.extract_u32()
+            .map_err(Error::length("size.0", buf.len()))?,
+        buf.extract_u32()
+            .map_err(Error::length("size.1", buf.len()))?,
+    );
+    let pixels = inflate_pixels(buf, size)?;
+
+    Ok(CanvasKeyframe { time, size, pixels })
+}
+
+fn ser_palette_insert(buf: &mut [u8], record: &PaletteInsert) -> Result<usize, Error> {
+    let len = buf.len();
     let mut buf = buf;
 
-    let offset = buf.extract_u32()?;
-    let mut colors = Vec::with_capacity(buf.len() / 4);
-    while !buf.is_empty() {
-        // Safety: extract(4) always returns a slice of len == 4
-        colors.push(buf.extract(4)?.try_into().unwrap());
+    buf.insert_u32(record.offset)
+        .map_err(Error::length("offset", buf.len()))?;
+    for color in &record.colors {
+        buf.insert(color)
+            .map_err(Error::length("colors", buf.len()))?;
     }
 
-    Ok(PaletteInsert { offset, colors })
+    Ok(len - buf.len())
+}
+
+fn des_palette_insert(buf: &[u8]) -> Result<PaletteInsertRef<'_>, Error> {
+    let mut buf = buf;
+
+    let offset = buf
+        .extract_u32()
+        .map_err(Error::length("offset", buf.len()))?;
+    let (colors, []) = buf.as_chunks::<4>() else {
+        return Err(Error::InvalidValueLength {
+            field: "colors",
+            expected: buf.len().next_multiple_of(4),
+            actual: buf.len(),
+        });
+    };
+
+    Ok(PaletteInsertRef { offset, colors })
 }
 
 fn ser_palette_remove(buf: &mut [u8], record: &PaletteRemove) -> Result<usize, Error> {
     let len = buf.len();
     let mut buf = buf;
 
-    buf.insert_u32(record.offset)?;
+    buf.insert_u32(record.offset)
+        .map_err(Error::length("offset", buf.len()))?;
     if record.length.get() > 1 {
-        buf.insert_u32(record.length.get())?;
+        buf.insert_u32(record.length.get())
+            .map_err(Error::length("length", buf.len()))?;
     }
 
     Ok(len - buf.len())
@@ -173,10 +586,11 @@ fn ser_palette_remove(buf: &mut [u8], record: &PaletteRemove) -> Result<usize, E
 fn des_palette_remove(buf: &[u8]) -> Result<PaletteRemove, Error> {
     let mut buf = buf;
 
-    let offset = buf.extract_u32()?;
+    let offset = buf
+        .extract_u32()
+        .map_err(Error::length("offset", buf.len()))?;
     let length = buf.extract_u32().unwrap_or(1);
-    let length =
-        NonZeroU32::new(length).ok_or(Error::InvalidField(length.to_le_bytes().to_vec()))?;
+    let length = NonZeroU32::new(length).ok_or(Error::field("length", &length.to_le_bytes()))?;
 
     Ok(PaletteRemove { offset, length })
 }
@@ -185,9 +599,12 @@ fn ser_placement_insert(buf: &mut [u8], record: &PlacementInsert) -> Result<usiz
     let len = buf.len();
     let mut buf = buf;
 
-    buf.insert_u64(record.time)?;
-    buf.insert_u64(record.pos)?;
-    buf.insert_u32(record.col)?;
+    buf.insert_u64(record.time)
+        .map_err(Error::length("time", buf.len()))?;
+    buf.insert_u64(record.pos)
+        .map_err(Error::length("pos", buf.len()))?;
+    buf.insert_u32(record.col)
+        .map_err(Error::length("col", buf.len()))?;
 
     Ok(len - buf.len())
 }
@@ -195,9 +612,11 @@ fn ser_placement_insert(buf: &mut [u8], record: &PlacementInsert) -> Result<usiz
 fn des_placement_insert(buf: &[u8]) -> Result<PlacementInsert, Error> {
     let mut buf = buf;
 
-    let time = buf.extract_u64()?;
-    let pos = buf.extract_u64()?;
-    let col = buf.extract_u32()?;
+    let time = buf
+        .extract_u64()
+        .map_err(Error::length("time", buf.len()))?;
+    let pos = buf.extract_u64().map_err(Error::length("pos", buf.len()))?;
+    let col = buf.extract_u32().map_err(Error::length("col", buf.len()))?;
 
     Ok(PlacementInsert { time, pos, col })
 }
@@ -206,10 +625,14 @@ fn ser_placement_insert_fill(buf: &mut [u8], record: &PlacementInsertFill) -> Re
     let len = buf.len();
     let mut buf = buf;
 
-    buf.insert_u64(record.time)?;
-    buf.insert_u64(record.pos.0)?;
-    buf.insert_u64(record.pos.1)?;
-    buf.insert_u32(record.col)?;
+    buf.insert_u64(record.time)
+        .map_err(Error::length("time", buf.len()))?;
+    buf.insert_u64(record.pos.0)
+        .map_err(Error::length("pos.0", buf.len()))?;
+    buf.insert_u64(record.pos.1)
+        .map_err(Error::length("pos.1", buf.len()))?;
+    buf.insert_u32(record.col)
+        .map_err(Error::length("col", buf.len()))?;
 
     Ok(len - buf.len())
 }
@@ -217,9 +640,16 @@ fn ser_placement_insert_fill(buf: &mut [u8], record: &PlacementInsertFill) -> Re
 fn des_placement_insert_fill(buf: &[u8]) -> Result<PlacementInsertFill, Error> {
     let mut buf = buf;
 
-    let time = buf.extract_u64()?;
-    let pos = (buf.extract_u64()?, buf.extract_u64()?);
-    let col = buf.extract_u32()?;
+    let time = buf
+        .extract_u64()
+        .map_err(Error::length("time", buf.len()))?;
+    let pos = (
+        buf.extract_u64()
+            .map_err(Error::length("pos.0", buf.len()))?,
+        buf.extract_u64()
+            .map_err(Error::length("pos.1", buf.len()))?,
+    );
+    let col = buf.extract_u32().map_err(Error::length("col", buf.len()))?;
 
     Ok(PlacementInsertFill { time, pos, col })
 }
@@ -228,8 +658,10 @@ fn ser_placement_remove(buf: &mut [u8], record: &PlacementRemove) -> Result<usiz
     let len = buf.len();
     let mut buf = buf;
 
-    buf.insert_u64(record.time)?;
-    buf.insert_u64(record.pos)?;
+    buf.insert_u64(record.time)
+        .map_err(Error::length("time", buf.len()))?;
+    buf.insert_u64(record.pos)
+        .map_err(Error::length("pos", buf.len()))?;
 
     Ok(len - buf.len())
 }
@@ -237,8 +669,10 @@ fn ser_placement_remove(buf: &mut [u8], record: &PlacementRemove) -> Result<usiz
 fn des_placement_remove(buf: &[u8]) -> Result<PlacementRemove, Error> {
     let mut buf = buf;
 
-    let time = buf.extract_u64()?;
-    let pos = buf.extract_u64()?;
+    let time = buf
+        .extract_u64()
+        .map_err(Error::length("time", buf.len()))?;
+    let pos = buf.extract_u64().map_err(Error::length("pos", buf.len()))?;
 
     Ok(PlacementRemove { time, pos })
 }
@@ -247,9 +681,12 @@ fn ser_placement_remove_fill(buf: &mut [u8], record: &PlacementRemoveFill) -> Re
     let len = buf.len();
     let mut buf = buf;
 
-    buf.insert_u64(record.time)?;
-    buf.insert_u64(record.pos.0)?;
-    buf.insert_u64(record.pos.1)?;
+    buf.insert_u64(record.time)
+        .map_err(Error::length("time", buf.len()))?;
+    buf.insert_u64(record.pos.0)
+        .map_err(Error::length("pos.0", buf.len()))?;
+    buf.insert_u64(record.pos.1)
+        .map_err(Error::length("pos.1", buf.len()))?;
 
     Ok(len - buf.len())
 }
@@ -257,56 +694,107 @@ fn ser_placement_remove_fill(buf: &mut [u8], record: &PlacementRemoveFill) -> Re
 fn des_placement_remove_fill(buf: &[u8]) -> Result<PlacementRemoveFill, Error> {
     let mut buf = buf;
 
-    let time = buf.extract_u64()?;
-    let pos = (buf.extract_u64()?, buf.extract_u64()?);
+    let time = buf
+        .extract_u64()
+        .map_err(Error::length("time", buf.len()))?;
+    let pos = (
+        buf.extract_u64()
+            .map_err(Error::length("pos.0", buf.len()))?,
+        buf.extract_u64()
+            .map_err(Error::length("pos.1", buf.len()))?,
+    );
 
     Ok(PlacementRemoveFill { time, pos })
 }
 
+fn ser_placement_insert_batch(buf: &mut [u8], batch: &[PlacementInsert]) -> Result<usize, Error> {
+    let mut written = 0;
+    for record in batch {
+        written += ser_placement_insert(&mut buf[written..], record)?;
+    }
+
+    Ok(written)
+}
+
+fn des_placement_insert_batch(buf: &[u8]) -> Result<Vec<PlacementInsert>, Error> {
+    if !buf.len().is_multiple_of(PLACEMENT_INSERT_LEN) {
+        return Err(Error::InvalidValueLength {
+            field: "placements",
+            expected: buf.len().next_multiple_of(PLACEMENT_INSERT_LEN),
+            actual: buf.len(),
+        });
+    }
+
+    buf.chunks_exact(PLACEMENT_INSERT_LEN)
+        .map(des_placement_insert)
+        .collect()
+}
+
+fn ser_placement_remove_batch(buf: &mut [u8], batch: &[PlacementRemove]) -> Result<usize, Error> {
+    let mut written = 0;
+    for record in batch {
+        written += ser_placement_remove(&mut buf[written..], record)?;
+    }
+
+    Ok(written)
+}
+
+fn des_placement_remove_batch(buf: &[u8]) -> Result<Vec<PlacementRemove>, Error> {
+    if !buf.len().is_multiple_of(PLACEMENT_REMOVE_LEN) {
+        return Err(Error::InvalidValueLength {
+            field: "placements",
+            expected: buf.len().next_multiple_of(PLACEMENT_REMOVE_LEN),
+            actual: buf.len(),
+        });
+    }
+
+    buf.chunks_exact(PLACEMENT_REMOVE_LEN)
+        .map(des_placement_remove)
+        .collect()
+}
+
 fn ser_identify_numeric(buf: &mut [u8], record: u64) -> Result<usize, Error> {
     let len = buf.len();
     let mut buf = buf;
-    buf.insert_u64(record)?;
+    buf.insert_u64(record)
+        .map_err(Error::length("id", buf.len()))?;
 
     Ok(len - buf.len())
 }
 
 fn des_identify_numeric(buf: &[u8]) -> Result<u64, Error> {
     let mut buf = buf;
-    buf.extract_u64().map_err(Error::from)
+    buf.extract_u64().map_err(Error::length("id", buf.len()))
 }
 
-fn ser_identify_string(buf: &mut [u8], record: &str) -> Result<usize, Error> {
+pub(super) fn ser_identify_string(buf: &mut [u8], record: &str) -> Result<usize, Error> {
     let len = buf.len();
     let mut buf = buf;
-    buf.insert(record.as_bytes())?;
+    buf.insert(record.as_bytes())
+        .map_err(Error::length("id", buf.len()))?;
 
     Ok(len - buf.len())
 }
 
-// TODO: Borrow?
-fn des_identify_string(buf: &[u8]) -> Result<String, Error> {
-    let mut buf = buf;
-    Ok(str::from_utf8(buf.extract(buf.len())?)?.to_string())
+pub(super) fn des_identify_string(buf: &[u8]) -> Result<&str, Error> {
+    str::from_utf8(buf).map_err(Error::utf8("id"))
 }
 
-fn ser_identify_secret(buf: &mut [u8], record: &[u8]) -> Result<usize, Error> {
+pub(super) fn ser_identify_secret(buf: &mut [u8], record: &[u8]) -> Result<usize, Error> {
     let len = buf.len();
     let mut buf = buf;
-    buf.insert(record)?;
+    buf.insert(record).map_err(Error::length("id", buf.len()))?;
 
     Ok(len - buf.len())
 }
 
-// TODO: Borrow?
-fn des_identify_secret(buf: &[u8]) -> Result<Vec<u8>, Error> {
-    let mut buf = buf;
-    Ok(buf.extract(buf.len())?.to_vec())
+pub(super) fn des_identify_secret(buf: &[u8]) -> Result<&[u8], Error> {
+    Ok(buf)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{PALETTE_REMOVE_TYPE_ID};
+    use crate::{PALETTE_REMOVE_TYPE_ID, PLACEMENT_INSERT_BATCH_TYPE_ID, PLACEMENT_INSERT_TYPE_ID};
 
     use super::*;
 
@@ -318,6 +806,14 @@ mod test {
 
         assert_eq!(record, sample);
 
+        let record_ref = serialiser
+            .deserialise_record_ref(sample.raw_id(), raw)
+            .expect("failed borrowed deserialise");
+
+        assert_eq!(record_ref.raw_id(), sample.raw_id());
+        assert_eq!(record_ref.to_owned(), sample);
+        assert_eq!(serialiser.encoded_len(&record), Ok(raw.len()));
+
         let mut buf = vec![0; raw.len()];
         let written = serialiser
             .serialise_record(buf.as_mut_slice(), &record)
@@ -325,16 +821,31 @@ mod test {
 
         assert_eq!(written, buf.len());
         assert_eq!(buf, raw);
+
+        let mut wtr = Vec::new();
+        serialiser
+            .write_record(&record, &mut wtr)
+            .expect("failed write");
+
+        assert_eq!(wtr, raw);
     }
 
-    fn ser_harness_err(expected_len: usize, sample: CanvasRecord, err: Error) {
+    fn ser_harness_err(sample: CanvasRecord, err: Error) {
         let serialiser = Serialiser;
-        let mut buf = vec![0; expected_len];
+        let mut buf = vec![0; serialiser.encoded_len(&sample).expect("failed measure")];
         let des_err = serialiser
             .serialise_record(buf.as_mut_slice(), &sample)
             .expect_err("succeeded serialise unexpectedly");
 
         assert_eq!(des_err, err);
+
+        let mut wtr = Vec::new();
+        let write_err = serialiser
+            .write_record(&sample, &mut wtr)
+            .expect_err("succeeded write unexpectedly");
+
+        assert!(matches!(write_err, IoError::Parse(e) if e == err));
+        assert!(wtr.is_empty());
     }
 
     fn des_harness_err(id: u16, raw: &[u8], err: Error) {
@@ -368,27 +879,115 @@ mod test {
         );
         // Illegal name lengths
         ser_harness_err(
-            288,
             CanvasRecord::CanvasMeta(CanvasMeta {
                 name: String::from_utf8(vec![0; 256]).unwrap(),
                 platform: "pxls.space".to_string(),
                 time: 1234,
                 size: (512, 256),
             }),
-            Error::InvalidField(256usize.to_le_bytes().to_vec()),
+            Error::field("name", &256usize.to_le_bytes()),
         );
         ser_harness_err(
-            289,
             CanvasRecord::CanvasMeta(CanvasMeta {
                 name: "test".to_string(),
                 platform: String::from_utf8(vec![0; 257]).unwrap(),
                 time: 1234,
                 size: (512, 256),
             }),
-            Error::InvalidField(257usize.to_le_bytes().to_vec()),
+            Error::field("platform", &257usize.to_le_bytes()),
         );
     }
 
+    #[test]
+    fn codec_canvas_resize() {
+        serdes_harness(
+            CanvasRecord::CanvasResize(CanvasResize {
+                time: 1234,
+                size: (1000, 500),
+                offset: (500, 0),
+            }),
+            constcat::concat_bytes!(
+                &1234u64.to_le_bytes(), // Time
+                &1000u32.to_le_bytes(), // Width
+                &500u32.to_le_bytes(),  // Height
+                &500u32.to_le_bytes(),  // Offset X
+                &0u32.to_le_bytes(),    // Offset Y
+            ),
+        );
+
+        des_harness_err(
+            crate::CANVAS_RESIZE_TYPE_ID,
+            &[0; 20],
+            Error::InvalidValueLength {
+                field: "offset.1",
+                expected: 4,
+                actual: 0,
+            },
+        );
+    }
+
+    #[test]
+    fn codec_canvas_keyframe() {
+        let pixels = constcat::concat_bytes!(
+            &0u32.to_le_bytes(), // Empty
+            &4u32.to_le_bytes(), // Color 3
+        );
+        let raw = [
+            constcat::concat_bytes!(
+                &1234u64.to_le_bytes(), // Time
+                &2u32.to_le_bytes(),    // Width
+                &1u32.to_le_bytes(),    // Height
+            )
+            .as_slice(),
+            &miniz_oxide::deflate::compress_to_vec(pixels, KEYFRAME_COMPRESSION_LEVEL),
+        ]
+        .concat();
+
+        serdes_harness(
+            CanvasRecord::CanvasKeyframe(CanvasKeyframe {
+                time: 1234,
+                size: (2, 1),
+                pixels: vec![None, Some(3)],
+            }),
+            &raw,
+        );
+
+        // Pixels do not fill the canvas
+        let mut raw = raw;
+        raw[12] = 2;
+        des_harness_err(
+            crate::CANVAS_KEYFRAME_TYPE_ID,
+            &raw,
+            Error::InvalidField {
+                field: "pixels",
+                value: raw[16..].iter().copied().take(16).collect(),
+            },
+        );
+
+        // Too many pixels to inflate
+        raw[8..16].copy_from_slice(&[0xFF; 8]);
+        des_harness_err(
+            crate::CANVAS_KEYFRAME_TYPE_ID,
+            &raw,
+            Error::InvalidField {
+                field: "size",
+                value: vec![0xFF; 8],
+            },
+        );
+
+        // Color that cannot be stored
+        let keyframe = CanvasRecord::CanvasKeyframe(CanvasKeyframe {
+            time: 1234,
+            size: (1, 1),
+            pixels: vec![Some(u32::MAX)],
+        });
+        let err = Error::InvalidField {
+            field: "pixels",
+            value: u32::MAX.to_le_bytes().to_vec(),
+        };
+        assert_eq!(Serialiser.encoded_len(&keyframe), Err(err));
+    }
+
     #[test]
     fn codec_palette_insert() {
         const COLORS: &[[u8; 4]] = &[
@@ -439,7 +1038,7 @@ mod test {
                 &16u32.to_le_bytes(), // Offset
                 &0u32.to_le_bytes(),  // Length (0 is invalid)
             ),
-            Error::InvalidField(0u32.to_le_bytes().to_vec()),
+            Error::field("length", &0u32.to_le_bytes()),
         );
     }
 
@@ -458,6 +1057,16 @@ mod test {
 
         serdes_harness(CanvasRecord::PlacementInsert(inner.clone()), raw);
         serdes_harness(CanvasRecord::PlacementInsertQuiet(inner), raw);
+        // Truncated color
+        des_harness_err(
+            PLACEMENT_INSERT_TYPE_ID,
+            &raw[..18],
+            Error::InvalidValueLength {
+                field: "col",
+                expected: 4,
+                actual: 2,
+            },
+        );
     }
 
     #[test]
@@ -509,6 +1118,68 @@ mod test {
         serdes_harness(CanvasRecord::PlacementRemoveFillQuiet(inner), raw);
     }
 
+    #[test]
+    fn codec_placement_insert_batch() {
+        let inner = vec![
+            PlacementInsert {
+                time: 1234,
+                pos: 21,
+                col: 5,
+            },
+            PlacementInsert {
+                time: 1235,
+                pos: 42,
+                col: 6,
+            },
+        ];
+        let raw = constcat::concat_bytes!(
+            &1234u64.to_le_bytes(), // Time 1
+            &21u64.to_le_bytes(),   // Position 1
+            &5u32.to_le_bytes(),    // Color 1
+            &1235u64.to_le_bytes(), // Time 2
+            &42u64.to_le_bytes(),   // Position 2
+            &6u32.to_le_bytes(),    // Color 2
+        );
+
+        serdes_harness(CanvasRecord::PlacementInsertBatch(inner.clone()), raw);
+        serdes_harness(CanvasRecord::PlacementInsertBatchQuiet(inner), raw);
+        // Empty
+        serdes_harness(CanvasRecord::PlacementInsertBatch(Vec::new()), &[]);
+        // Trailing partial placement
+        des_harness_err(
+            PLACEMENT_INSERT_BATCH_TYPE_ID,
+            &raw[..raw.len() - 1],
+            Error::InvalidValueLength {
+                field: "placements",
+                expected: 40,
+                actual: 39,
+            },
+        );
+    }
+
+    #[test]
+    fn codec_placement_remove_batch() {
+        let inner = vec![
+            PlacementRemove {
+                time: 1234,
+                pos: 21,
+            },
+            PlacementRemove {
+                time: 1235,
+                pos: 42,
+            },
+        ];
+        let raw = constcat::concat_bytes!(
+            &1234u64.to_le_bytes(), // Time 1
+            &21u64.to_le_bytes(),   // Position 1
+            &1235u64.to_le_bytes(), // Time 2
+            &42u64.to_le_bytes(),   // Position 2
+        );
+
+        serdes_harness(CanvasRecord::PlacementRemoveBatch(inner.clone()), raw);
+        serdes_harness(CanvasRecord::PlacementRemoveBatchQuiet(inner), raw);
+    }
+
     #[test]
     fn codec_identifier() {
         serdes_harness(
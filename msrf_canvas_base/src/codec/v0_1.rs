@@ -0,0 +1,1100 @@
+use std::{cell::Cell, io::Write, num::NonZeroU32};
+
+use msrf::error::IoError;
+
+use super::{Error, RawSerialiser, v0_0};
+use crate::{
+    CanvasKeyframe, CanvasMeta, CanvasRecord, CanvasRecordRef, CanvasResize, PaletteInsert,
+    PaletteInsertRef, PaletteRemove, PlacementInsert, PlacementInsertFill, PlacementRemove,
+    PlacementRemoveFill,
+};
+
+// Longest valid LEB128 encoding of a u64
+const VARINT_MAX_LEN: usize = 10;
+
+/// Compact codec storing placement times as deltas and integers as LEB128 varints.
+///
+/// Times are relative to the previous timestamped record handled by this serialiser (or the
+/// last [`CanvasMeta`](crate::CanvasMeta)), so a serialiser must see every record of a stream
+/// in order and must not be shared between a reader and a writer.
+///
+/// That base time is mutable state behind `&self` (a [`Cell`]), as [`RecordSerialise`] takes
+/// `&self`. The serialiser is therefore not `Sync`, and the result of every call depends on the
+/// calls before it: serialising or deserialising a record advances the base time, and
+/// [`encoded_len`](Self::encoded_len) measures against it without advancing it. Use
+/// [`time`](Self::time) and [`set_time`](Self::set_time) to save and restore it, for example
+/// around a seek.
+///
+/// Integers are encoded canonically, in the fewest bytes, and longer encodings are rejected so
+/// that every value has exactly one encoding.
+#[derive(Debug, Default, Clone)]
+pub struct Serialiser {
+    time: Cell<u64>,
+}
+
+impl Serialiser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time that the next record's delta is relative to.
+    pub fn time(&self) -> u64 {
+        self.time.get()
+    }
+
+    /// Resume a stream at a record whose delta is relative to `time`, such as after seeking.
+    pub fn set_time(&self, time: u64) {
+        self.time.set(time);
+    }
+
+    /// Decode a record borrowing its strings and byte fields from `value`.
+    pub fn deserialise_record_ref<'a>(
+        &self,
+        id: u16,
+        value: &'a [u8],
+    ) -> Result<CanvasRecordRef<'a>, Error> {
+        let mut time = self.time.get();
+        let record = match id {
+            crate::CANVAS_META_TYPE_ID => {
+                let meta = v0_0::des_canvas_meta(value)?;
+                time = meta.time;
+                CanvasRecordRef::CanvasMeta(meta)
+            }
+            crate::CANVAS_RESIZE_TYPE_ID => {
+                CanvasRecordRef::CanvasResize(des_canvas_resize(value, &mut time)?)
+            }
+            crate::CANVAS_KEYFRAME_TYPE_ID => {
+                CanvasRecordRef::CanvasKeyframe(des_canvas_keyframe(value, &mut time)?)
+            }
+            crate::PALETTE_INSERT_TYPE_ID => {
+                CanvasRecordRef::PaletteInsert(des_palette_insert(value)?)
+            }
+            crate::PALETTE_REMOVE_TYPE_ID => {
+                CanvasRecordRef::PaletteRemove(des_palette_remove(value)?)
+            }
+            crate::PLACEMENT_INSERT_TYPE_ID => {
+                CanvasRecordRef::PlacementInsert(des_placement_insert(value, &mut time)?)
+            }
+            crate::PLACEMENT_INSERT_SILENT_TYPE_ID => {
+                CanvasRecordRef::PlacementInsertQuiet(des_placement_insert(value, &mut time)?)
+            }
+            crate::PLACEMENT_INSERT_FILL_TYPE_ID => {
+                CanvasRecordRef::PlacementInsertFill(des_placement_insert_fill(value, &mut time)?)
+            }
+            crate::PLACEMENT_INSERT_FILL_SILENT_TYPE_ID => {
+                CanvasRecordRef::PlacementInsertFillQuiet(des_placement_insert_fill(
+                    value, &mut time,
+                )?)
+            }
+            crate::PLACEMENT_REMOVE_TYPE_ID => {
+                CanvasRecordRef::PlacementRemove(des_placement_remove(value, &mut time)?)
+            }
+            crate::PLACEMENT_REMOVE_SILENT_TYPE_ID => {
+                CanvasRecordRef::PlacementRemoveQuiet(des_placement_remove(value, &mut time)?)
+            }
+            crate::PLACEMENT_REMOVE_FILL_TYPE_ID => {
+                CanvasRecordRef::PlacementRemoveFill(des_placement_remove_fill(value, &mut time)?)
+            }
+            crate::PLACEMENT_REMOVE_FILL_SILENT_TYPE_ID => {
+                CanvasRecordRef::PlacementRemoveFillQuiet(des_placement_remove_fill(
+                    value, &mut time,
+                )?)
+            }
+            crate::PLACEMENT_INSERT_BATCH_TYPE_ID => {
+                CanvasRecordRef::PlacementInsertBatch(des_placement_insert_batch(value, &mut time)?)
+            }
+            crate::PLACEMENT_INSERT_BATCH_SILENT_TYPE_ID => {
+                CanvasRecordRef::PlacementInsertBatchQuiet(des_placement_insert_batch(
+                    value, &mut time,
+                )?)
+            }
+            crate::PLACEMENT_REMOVE_BATCH_TYPE_ID => {
+                CanvasRecordRef::PlacementRemoveBatch(des_placement_remove_batch(value, &mut time)?)
+            }
+            crate::PLACEMENT_REMOVE_BATCH_SILENT_TYPE_ID => {
+                CanvasRecordRef::PlacementRemoveBatchQuiet(des_placement_remove_batch(
+                    value, &mut time,
+                )?)
+            }
+            crate::IDENTIFIER_NUMERIC_TYPE_ID => {
+                CanvasRecordRef::IdentifierNumeric(des_identify_numeric(value)?)
+            }
+            crate::IDENTIFIER_STRING_TYPE_ID => {
+                CanvasRecordRef::IdentifierString(v0_0::des_identify_string(value)?)
+            }
+            crate::IDENTIFIER_SECRET_TYPE_ID => {
+                CanvasRecordRef::IdentifierSecret(v0_0::des_identify_secret(value)?)
+            }
+            _ => return Err(Error::UnexpectedType(id)),
+        };
+
+        self.time.set(time);
+        Ok(record)
+    }
+
+    /// Exact number of bytes [`RecordSerialise::serialise_record`] would write for `record`
+    /// given the current [`time`](Self::time), or an error if a keyframe fails to compress.
+    pub fn encoded_len(&self, record: &CanvasRecord) -> Result<usize, Error> {
+        let mut time = self.time.get();
+        let mut time_len = |next: u64| {
+            let len = varint_len(zigzag(next.wrapping_sub(time)));
+            time = next;
+            len
+        };
+
+        Ok(match record {
+            CanvasRecord::CanvasMeta(_) => v0_0::Serialiser.encoded_len(record)?,
+            CanvasRecord::CanvasResize(rec) => {
+                time_len(rec.time)
+                    + varint_len(rec.size.0 as u64)
+                    + varint_len(rec.size.1 as u64)
+                    + varint_len(rec.offset.0 as u64)
+                    + varint_len(rec.offset.1 as u64)
+            }
+            CanvasRecord::CanvasKeyframe(rec) => {
+                time_len(rec.time)
+                    + varint_len(rec.size.0 as u64)
+                    + varint_len(rec.size.1 as u64)
+                    + v0_0::deflate_pixels(&rec.pixels)?.len()
+            }
+            CanvasRecord::PaletteInsert(rec) => {
+                varint_len(rec.offset as u64) + rec.colors.len() * 4
+            }
+            CanvasRecord::PaletteRemove(rec) => {
+                varint_len(rec.offset as u64)
+                    + if rec.length.get() > 1 {
+                        varint_len(rec.length.get() as u64)
+                    } else {
+                        0
+                    }
+            }
+            CanvasRecord::PlacementInsert(rec) | CanvasRecord::PlacementInsertQuiet(rec) => {
+                time_len(rec.time) + varint_len(rec.pos) + varint_len(rec.col as u64)
+            }
+            CanvasRecord::PlacementInsertFill(rec)
+            | CanvasRecord::PlacementInsertFillQuiet(rec) => {
+                time_len(rec.time)
+                    + varint_len(rec.pos.0)
+                    + varint_len(rec.pos.1)
+                    + varint_len(rec.col as u64)
+            }
+            CanvasRecord::PlacementRemove(rec) | CanvasRecord::PlacementRemoveQuiet(rec) => {
+                time_len(rec.time) + varint_len(rec.pos)
+            }
+            CanvasRecord::PlacementRemoveFill(rec)
+            | CanvasRecord::PlacementRemoveFillQuiet(rec) => {
+                time_len(rec.time) + varint_len(rec.pos.0) + varint_len(rec.pos.1)
+            }
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => batch
+                .iter()
+                .map(|rec| time_len(rec.time) + varint_len(rec.pos) + varint_len(rec.col as u64))
+                .sum(),
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => batch
+                .iter()
+                .map(|rec| time_len(rec.time) + varint_len(rec.pos))
+                .sum(),
+            CanvasRecord::IdentifierNumeric(n) => varint_len(*n),
+            CanvasRecord::IdentifierString(s) => s.len(),
+            CanvasRecord::IdentifierSecret(raw) => raw.len(),
+        })
+    }
+}
+
+impl RecordSerialise for Serialiser {
+    type Err = Error;
+
+    type Record = CanvasRecord;
+
+    fn deserialise_record(&self, id: u16, value: &[u8]) -> Result<Self::Record, Self::Err> {
+        self.deserialise_record_ref(id, value)
+            .map(CanvasRecordRef::into_owned)
+    }
+
+    fn serialise_record(
+        &self,
+        value: &mut [u8],
+        record: &Self::Record,
+    ) -> Result<usize, Self::Err> {
+        let mut time = self.time.get();
+        let written = match record {
+            CanvasRecord::CanvasMeta(canvas_meta) => {
+                time = canvas_meta.time;
+                v0_0::ser_canvas_meta(value, canvas_meta)
+            }
+            CanvasRecord::CanvasResize(canvas_resize) => {
+                ser_canvas_resize(value, canvas_resize, &mut time)
+            }
+            CanvasRecord::CanvasKeyframe(canvas_keyframe) => {
+                ser_canvas_keyframe(value, canvas_keyframe, &mut time)
+            }
+            CanvasRecord::PaletteInsert(palette_insert) => {
+                ser_palette_insert(value, palette_insert)
+            }
+            CanvasRecord::PaletteRemove(palette_remove) => {
+                ser_palette_remove(value, palette_remove)
+            }
+            CanvasRecord::PlacementInsert(placement_insert)
+            | CanvasRecord::PlacementInsertQuiet(placement_insert) => {
+                ser_placement_insert(value, placement_insert, &mut time)
+            }
+            CanvasRecord::PlacementInsertFill(placement_insert_fill)
+            | CanvasRecord::PlacementInsertFillQuiet(placement_insert_fill) => {
+                ser_placement_insert_fill(value, placement_insert_fill, &mut time)
+            }
+            CanvasRecord::PlacementRemove(placement_remove)
+            | CanvasRecord::PlacementRemoveQuiet(placement_remove) => {
+                ser_placement_remove(value, placement_remove, &mut time)
+            }
+            CanvasRecord::PlacementRemoveFill(placement_remove_fill)
+            | CanvasRecord::PlacementRemoveFillQuiet(placement_remove_fill) => {
+                ser_placement_remove_fill(value, placement_remove_fill, &mut time)
+            }
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => {
+                ser_placement_insert_batch(value, batch, &mut time)
+            }
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => {
+                ser_placement_remove_batch(value, batch, &mut time)
+            }
+            CanvasRecord::IdentifierNumeric(n) => ser_identify_numeric(value, *n),
+            CanvasRecord::IdentifierString(s) => v0_0::ser_identify_string(value, s),
+            CanvasRecord::IdentifierSecret(raw) => v0_0::ser_identify_secret(value, raw),
+        }?;
+
+        self.time.set(time);
+        Ok(written)
+    }
+}
+
+impl Serialiser {
+    // Write the varint fields encoded by `ser` followed by `tail`, advancing the base time only
+    // once both are written
+    fn write_fields<W: Write>(
+        &self,
+        mut wtr: W,
+        tail: &[u8],
+        ser: impl FnOnce(&mut [u8], &mut u64) -> Result<usize, Error>,
+    ) -> Result<(), IoError<Error>> {
+        // No record has more than five varint fields
+        let mut buf = [0; 5 * VARINT_MAX_LEN];
+        let mut time = self.time.get();
+        let written = ser(&mut buf, &mut time).map_err(IoError::Parse)?;
+
+        wtr.write_all(&buf[..written])?;
+        wtr.write_all(tail)?;
+        self.time.set(time);
+
+        Ok(())
+    }
+
+    // Encode a whole batch of entries with up to `fields` varints each before writing it, so the
+    // time only advances once every entry has been written
+    fn write_batch<W: Write>(
+        &self,
+        mut wtr: W,
+        len: usize,
+        fields: usize,
+        ser: impl FnOnce(&mut [u8], &mut u64) -> Result<usize, Error>,
+    ) -> Result<(), IoError<Error>> {
+        let mut buf = vec![0; len * fields * VARINT_MAX_LEN];
+        let mut time = self.time.get();
+        let written = ser(&mut buf, &mut time).map_err(IoError::Parse)?;
+
+        wtr.write_all(&buf[..written])?;
+        self.time.set(time);
+
+        Ok(())
+    }
+}
+
+impl RawSerialiser for Serialiser {
+    fn write_canvas_meta<W: Write>(&self, rec: &CanvasMeta, wtr: W) -> Result<(), IoError<Error>> {
+        v0_0::Serialiser.write_canvas_meta(rec, wtr)?;
+        self.time.set(rec.time);
+
+        Ok(())
+    }
+
+    fn write_canvas_resize<W: Write>(
+        &self,
+        rec: &CanvasResize,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, &[], |buf, time| ser_canvas_resize(buf, rec, time))
+    }
+
+    fn write_canvas_keyframe<W: Write>(
+        &self,
+        rec: &CanvasKeyframe,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        let data = v0_0::deflate_pixels(&rec.pixels).map_err(IoError::Parse)?;
+        self.write_fields(wtr, &data, |buf, time| {
+            let len = buf.len();
+            let mut buf = buf;
+
+            insert_time(&mut buf, rec.time, time)?;
+            insert_varint(&mut buf, "size.0", rec.size.0 as u64)?;
+            insert_varint(&mut buf, "size.1", rec.size.1 as u64)?;
+
+            Ok(len - buf.len())
+        })
+    }
+
+    fn write_palette_insert<W: Write>(
+        &self,
+        rec: &PaletteInsert,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, rec.colors.as_flattened(), |buf, _| {
+            let len = buf.len();
+            let mut buf = buf;
+            insert_varint(&mut buf, "offset", rec.offset as u64)?;
+
+            Ok(len - buf.len())
+        })
+    }
+
+    fn write_palette_remove<W: Write>(
+        &self,
+        rec: &PaletteRemove,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, &[], |buf, _| ser_palette_remove(buf, rec))
+    }
+
+    fn write_placement_insert<W: Write>(
+        &self,
+        rec: &PlacementInsert,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, &[], |buf, time| ser_placement_insert(buf, rec, time))
+    }
+
+    fn write_placement_insert_fill<W: Write>(
+        &self,
+        rec: &PlacementInsertFill,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, &[], |buf, time| {
+            ser_placement_insert_fill(buf, rec, time)
+        })
+    }
+
+    fn write_placement_remove<W: Write>(
+        &self,
+        rec: &PlacementRemove,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, &[], |buf, time| ser_placement_remove(buf, rec, time))
+    }
+
+    fn write_placement_remove_fill<W: Write>(
+        &self,
+        rec: &PlacementRemoveFill,
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, &[], |buf, time| {
+            ser_placement_remove_fill(buf, rec, time)
+        })
+    }
+
+    fn write_placement_insert_batch<W: Write>(
+        &self,
+        rec: &[PlacementInsert],
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_batch(wtr, rec.len(), 3, |buf, time| {
+            ser_placement_insert_batch(buf, rec, time)
+        })
+    }
+
+    fn write_placement_remove_batch<W: Write>(
+        &self,
+        rec: &[PlacementRemove],
+        wtr: W,
+    ) -> Result<(), IoError<Error>> {
+        self.write_batch(wtr, rec.len(), 2, |buf, time| {
+            ser_placement_remove_batch(buf, rec, time)
+        })
+    }
+
+    fn write_identifier_numeric<W: Write>(&self, rec: u64, wtr: W) -> Result<(), IoError<Error>> {
+        self.write_fields(wtr, &[], |buf, _| ser_identify_numeric(buf, rec))
+    }
+
+    fn write_identifier_string<W: Write>(&self, rec: &str, wtr: W) -> Result<(), IoError<Error>> {
+        v0_0::Serialiser.write_identifier_string(rec, wtr)
+    }
+
+    fn write_identifier_secret<W: Write>(&self, rec: &[u8], wtr: W) -> Result<(), IoError<Error>> {
+        v0_0::Serialiser.write_identifier_secret(rec, wtr)
+    }
+}
+
+fn varint_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).div_ceil(7).max(1)
+}
+
+fn zigzag(delta: u64) -> u64 {
+    let delta = delta as i64;
+    ((delta << 1) ^ (delta >> 63)) as u64
+}
+
+fn insert_varint(buf: &mut &mut [u8], field: &'static str, value: u64) -> Result<(), Error> {
+    let mut value = value;
+    while value >= 0x80 {
+        buf.insert_u8(value as u8 | 0x80)
+            .map_err(Error::length(field, buf.len()))?;
+        value >>= 7;
+    }
+    buf.insert_u8(value as u8)
+        .map_err(Error::length(field, buf.len()))?;
+
+    Ok(())
+}
+
+fn extract_varint(buf: &mut &[u8], field: &'static str) -> Result<u64, Error> {
+    let raw = *buf;
+    let mut value = 0u64;
+    for i in 0..VARINT_MAX_LEN {
+        let byte = buf.extract_u8().map_err(Error::length(field, buf.len()))?;
+        value |= ((byte & 0x7F) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            // The final byte of a 10 byte varint may only carry the top bit of a u64, and a
+            // trailing zero byte is an over-long encoding of a shorter varint
+            if (i == VARINT_MAX_LEN - 1 && byte > 1) || (i > 0 && byte == 0) {
+                break;
+            }
+            return Ok(value);
+        }
+    }
+
+    Err(Error::field(field, &raw[..VARINT_MAX_LEN.min(raw.len())]))
+}
+
+fn extract_varint_u32(buf: &mut &[u8], field: &'static str) -> Result<u32, Error> {
+    let value = extract_varint(buf, field)?;
+    value
+        .try_into()
+        .map_err(|_| Error::field(field, &value.to_le_bytes()))
+}
+
+fn insert_time(buf: &mut &mut [u8], time: u64, prev: &mut u64) -> Result<(), Error> {
+    // Zigzag encode so that small backwards steps stay small
+    insert_varint(buf, "time", zigzag(time.wrapping_sub(*prev)))?;
+    *prev = time;
+
+    Ok(())
+}
+
+fn extract_time(buf: &mut &[u8], prev: &mut u64) -> Result<u64, Error> {
+    let zigzag = extract_varint(buf, "time")?;
+    let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+    *prev = prev.wrapping_add(delta as u64);
+
+    Ok(*prev)
+}
+
+fn ser_canvas_resize(
+    buf: &mut [u8],
+    record: &CanvasResize,
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    insert_time(&mut buf, record.time, time)?;
+    insert_varint(&mut buf, "size.0", record.size.0 as u64)?;
+    insert_varint(&mut buf, "size.1", record.size.1 as u64)?;
+    insert_varint(&mut buf, "offset.0", record.offset.0 as u64)?;
+    insert_varint(&mut buf, "offset.1", record.offset.1 as u64)?;
+
+    Ok(len - buf.len())
+}
+
+fn des_canvas_resize(buf: &[u8], time: &mut u64) -> Result<CanvasResize, Error> {
+    let mut buf = buf;
+
+    let time = extract_time(&mut buf, time)?;
+    let size = (
+        extract_varint_u32(&mut buf, "size.0")?,
+        extract_varint_u32(&mut buf, "size.1")?,
+    );
+    let offset = (
+        extract_varint_u32(&mut buf, "offset.0")?,
+        extract_varint_u32(&mut buf, "offset.1")?,
+    );
+
+    Ok(CanvasResize { time, size, offset })
+}
+
+fn ser_canvas_keyframe(
+    buf: &mut [u8],
+    record: &CanvasKeyframe,
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    let data = v0_0::deflate_pixels(&record.pixels)?;
+    insert_time(&mut buf, record.time, time)?;
+    insert_varint(&mut buf, "size.0", record.size.0 as u64)?;
+    insert_varint(&mut buf, "size.1", record.size.1 as u64)?;
+    buf.insert(&data)
+        .map_err(Error::length("pixels", buf.len()))?;
+
+    Ok(len - buf.len())
+}
+
+fn des_canvas_keyframe(buf: &[u8], time: &mut u64) -> Result<CanvasKeyframe, Error> {
+    let mut buf = buf;
+
+    let time = extract_time(&mut buf, time)?;
+    let size = (
+        extract_varint_u32(&mut buf, "size.0")?,
+        extract_varint_u32(&mut buf, "size.1")?,
+    );
+    let pixels = v0_0::inflate_pixels(buf, size)?;
+
+    Ok(CanvasKeyframe { time, size, pixels })
+}
+
+fn ser_palette_insert(buf: &mut [u8], record: &PaletteInsert) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    insert_varint(&mut buf, "offset", record.offset as u64)?;
+    for color in &record.colors {
+        buf.insert(color)
+            .map_err(Error::length("colors", buf.len()))?;
+    }
+
+    Ok(len - buf.len())
+}
+
+fn des_palette_insert(buf: &[u8]) -> Result<PaletteInsertRef<'_>, Error> {
+    let mut buf = buf;
+
+    let offset = extract_varint_u32(&mut buf, "offset")?;
+    let (colors, []) = buf.as_chunks::<4>() else {
+        return Err(Error::InvalidValueLength {
+            field: "colors",
+            expected: buf.len().next_multiple_of(4),
+            actual: buf.len(),
+        });
+    };
+
+    Ok(PaletteInsertRef { offset, colors })
+}
+
+fn ser_palette_remove(buf: &mut [u8], record: &PaletteRemove) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    insert_varint(&mut buf, "offset", record.offset as u64)?;
+    if record.length.get() > 1 {
+        insert_varint(&mut buf, "length", record.length.get() as u64)?;
+    }
+
+    Ok(len - buf.len())
+}
+
+fn des_palette_remove(buf: &[u8]) -> Result<PaletteRemove, Error> {
+    let mut buf = buf;
+
+    let offset = extract_varint_u32(&mut buf, "offset")?;
+    let length = if buf.is_empty() {
+        1
+    } else {
+        extract_varint_u32(&mut buf, "length")?
+    };
+    let length = NonZeroU32::new(length).ok_or(Error::field("length", &length.to_le_bytes()))?;
+
+    Ok(PaletteRemove { offset, length })
+}
+
+fn ser_placement_insert(
+    buf: &mut [u8],
+    record: &PlacementInsert,
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    insert_time(&mut buf, record.time, time)?;
+    insert_varint(&mut buf, "pos", record.pos)?;
+    insert_varint(&mut buf, "col", record.col as u64)?;
+
+    Ok(len - buf.len())
+}
+
+fn extract_placement_insert(buf: &mut &[u8], time: &mut u64) -> Result<PlacementInsert, Error> {
+    let time = extract_time(buf, time)?;
+    let pos = extract_varint(buf, "pos")?;
+    let col = extract_varint_u32(buf, "col")?;
+
+    Ok(PlacementInsert { time, pos, col })
+}
+
+fn des_placement_insert(buf: &[u8], time: &mut u64) -> Result<PlacementInsert, Error> {
+    let mut buf = buf;
+    extract_placement_insert(&mut buf, time)
+}
+
+fn ser_placement_insert_fill(
+    buf: &mut [u8],
+    record: &PlacementInsertFill,
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    insert_time(&mut buf, record.time, time)?;
+    insert_varint(&mut buf, "pos.0", record.pos.0)?;
+    insert_varint(&mut buf, "pos.1", record.pos.1)?;
+    insert_varint(&mut buf, "col", record.col as u64)?;
+
+    Ok(len - buf.len())
+}
+
+fn des_placement_insert_fill(buf: &[u8], time: &mut u64) -> Result<PlacementInsertFill, Error> {
+    let mut buf = buf;
+
+    let time = extract_time(&mut buf, time)?;
+    let pos = (
+        extract_varint(&mut buf, "pos.0")?,
+        extract_varint(&mut buf, "pos.1")?,
+    );
+    let col = extract_varint_u32(&mut buf, "col")?;
+
+    Ok(PlacementInsertFill { time, pos, col })
+}
+
+fn ser_placement_remove(
+    buf: &mut [u8],
+    record: &PlacementRemove,
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    insert_time(&mut buf, record.time, time)?;
+    insert_varint(&mut buf, "pos", record.pos)?;
+
+    Ok(len - buf.len())
+}
+
+fn extract_placement_remove(buf: &mut &[u8], time: &mut u64) -> Result<PlacementRemove, Error> {
+    let time = extract_time(buf, time)?;
+    let pos = extract_varint(buf, "pos")?;
+
+    Ok(PlacementRemove { time, pos })
+}
+
+fn des_placement_remove(buf: &[u8], time: &mut u64) -> Result<PlacementRemove, Error> {
+    let mut buf = buf;
+    extract_placement_remove(&mut buf, time)
+}
+
+fn ser_placement_remove_fill(
+    buf: &mut [u8],
+    record: &PlacementRemoveFill,
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+
+    insert_time(&mut buf, record.time, time)?;
+    insert_varint(&mut buf, "pos.0", record.pos.0)?;
+    insert_varint(&mut buf, "pos.1", record.pos.1)?;
+
+    Ok(len - buf.len())
+}
+
+fn des_placement_remove_fill(buf: &[u8], time: &mut u64) -> Result<PlacementRemoveFill, Error> {
+    let mut buf = buf;
+
+    let time = extract_time(&mut buf, time)?;
+    let pos = (
+        extract_varint(&mut buf, "pos.0")?,
+        extract_varint(&mut buf, "pos.1")?,
+    );
+
+    Ok(PlacementRemoveFill { time, pos })
+}
+
+fn ser_placement_insert_batch(
+    buf: &mut [u8],
+    batch: &[PlacementInsert],
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let mut written = 0;
+    for record in batch {
+        written += ser_placement_insert(&mut buf[written..], record, time)?;
+    }
+
+    Ok(written)
+}
+
+fn des_placement_insert_batch(buf: &[u8], time: &mut u64) -> Result<Vec<PlacementInsert>, Error> {
+    let mut buf = buf;
+
+    let mut batch = Vec::new();
+    while !buf.is_empty() {
+        batch.push(extract_placement_insert(&mut buf, time)?);
+    }
+
+    Ok(batch)
+}
+
+fn ser_placement_remove_batch(
+    buf: &mut [u8],
+    batch: &[PlacementRemove],
+    time: &mut u64,
+) -> Result<usize, Error> {
+    let mut written = 0;
+    for record in batch {
+        written += ser_placement_remove(&mut buf[written..], record, time)?;
+    }
+
+    Ok(written)
+}
+
+fn des_placement_remove_batch(buf: &[u8], time: &mut u64) -> Result<Vec<PlacementRemove>, Error> {
+    let mut buf = buf;
+
+    let mut batch = Vec::new();
+    while !buf.is_empty() {
+        batch.push(extract_placement_remove(&mut buf, time)?);
+    }
+
+    Ok(batch)
+}
+
+fn ser_identify_numeric(buf: &mut [u8], record: u64) -> Result<usize, Error> {
+    let len = buf.len();
+    let mut buf = buf;
+    insert_varint(&mut buf, "id", record)?;
+
+    Ok(len - buf.len())
+}
+
+fn des_identify_numeric(buf: &[u8]) -> Result<u64, Error> {
+    let mut buf = buf;
+    extract_varint(&mut buf, "id")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CanvasMeta, PLACEMENT_INSERT_TYPE_ID};
+
+    use super::*;
+
+    fn serdes_harness(sample: CanvasRecord, raw: &[u8]) {
+        serdes_harness_with(&Serialiser::new(), &Serialiser::new(), sample, raw);
+    }
+
+    fn serdes_harness_with(
+        serialiser: &Serialiser,
+        deserialiser: &Serialiser,
+        sample: CanvasRecord,
+        raw: &[u8],
+    ) {
+        let borrowed = deserialiser.clone();
+        let writer = serialiser.clone();
+        let record = deserialiser
+            .deserialise_record(sample.raw_id(), raw)
+            .expect("failed deserialise");
+
+        assert_eq!(record, sample);
+        assert_eq!(
+            borrowed
+                .deserialise_record_ref(sample.raw_id(), raw)
+                .map(|record| record.to_owned()),
+            Ok(sample)
+        );
+        assert_eq!(serialiser.encoded_len(&record), Ok(raw.len()));
+
+        let mut buf = vec![0; raw.len()];
+        let written = serialiser
+            .serialise_record(buf.as_mut_slice(), &record)
+            .expect("failed serialise");
+
+        assert_eq!(written, buf.len());
+        assert_eq!(buf, raw);
+
+        let mut wtr = Vec::new();
+        writer
+            .write_record(&record, &mut wtr)
+            .expect("failed write");
+
+        assert_eq!(wtr, raw);
+        assert_eq!(writer.time(), serialiser.time());
+    }
+
+    fn des_harness_err(id: u16, raw: &[u8], err: Error) {
+        let serialiser = Serialiser::new();
+        let ser_err = serialiser
+            .deserialise_record(id, raw)
+            .expect_err("succeeded deserialise unexpectedly");
+
+        assert_eq!(ser_err, err);
+    }
+
+    #[test]
+    fn codec_varint() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as u64, u64::MAX] {
+            let mut raw = [0; VARINT_MAX_LEN];
+            let mut buf = raw.as_mut_slice();
+            insert_varint(&mut buf, "value", value).unwrap();
+            let written = VARINT_MAX_LEN - buf.len();
+
+            assert_eq!(varint_len(value), written);
+
+            let mut buf = &raw[..written];
+            assert_eq!(extract_varint(&mut buf, "value"), Ok(value));
+            assert!(buf.is_empty());
+        }
+
+        // Too long
+        let raw = [0xFF; VARINT_MAX_LEN + 1];
+        assert!(extract_varint(&mut raw.as_slice(), "value").is_err());
+        // Overflows u64
+        let raw = constcat::concat_bytes!(&[0xFF; VARINT_MAX_LEN - 1], &[0x02]);
+        assert!(extract_varint(&mut raw.as_slice(), "value").is_err());
+        // Over-long encodings of 0 and 1
+        assert!(extract_varint(&mut [0x80, 0x00].as_slice(), "value").is_err());
+        assert!(extract_varint(&mut [0x81, 0x80, 0x00].as_slice(), "value").is_err());
+    }
+
+    #[test]
+    fn codec_canvas_meta() {
+        let serialiser = Serialiser::new();
+        let deserialiser = Serialiser::new();
+        serdes_harness_with(
+            &serialiser,
+            &deserialiser,
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 1234,
+                size: (512, 256),
+            }),
+            constcat::concat_bytes!(
+                &[4u8],                   // Name Len
+                b"test".as_slice(),       // Name
+                &[10u8],                  // Platform Name Len
+                b"pxls.space".as_slice(), // Platform Name
+                &1234u64.to_le_bytes(),   // Time
+                &512u32.to_le_bytes(),    // Size.0
+                &256u32.to_le_bytes(),    // Size.1
+            ),
+        );
+
+        // Meta time is the base for following deltas
+        assert_eq!(serialiser.time(), 1234);
+        assert_eq!(deserialiser.time(), 1234);
+
+        serdes_harness_with(
+            &serialiser,
+            &deserialiser,
+            CanvasRecord::CanvasResize(CanvasResize {
+                time: 1300,
+                size: (1024, 256),
+                offset: (512, 0),
+            }),
+            &[
+                0x84, 0x01, // Time (+66)
+                0x80, 0x08, // Width
+                0x80, 0x02, // Height
+                0x80, 0x04, // Offset X
+                0x00, // Offset Y
+            ],
+        );
+        assert_eq!(serialiser.time(), 1300);
+        assert_eq!(deserialiser.time(), 1300);
+
+        let pixels = constcat::concat_bytes!(&0u32.to_le_bytes(), &4u32.to_le_bytes());
+        serdes_harness_with(
+            &serialiser,
+            &deserialiser,
+            CanvasRecord::CanvasKeyframe(CanvasKeyframe {
+                time: 1310,
+                size: (1, 2),
+                pixels: vec![None, Some(3)],
+            }),
+            &[
+                &[
+                    0x14, // Time (+10)
+                    0x01, // Width
+                    0x02, // Height
+                ],
+                miniz_oxide::deflate::compress_to_vec(pixels, 6).as_slice(),
+            ]
+            .concat(),
+        );
+        assert_eq!(serialiser.time(), 1310);
+    }
+
+    #[test]
+    fn codec_palette() {
+        serdes_harness(
+            CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: 200,
+                colors: vec![[0xFF; 4]],
+            }),
+            &[0xC8, 0x01, 0xFF, 0xFF, 0xFF, 0xFF],
+        );
+        // Short form
+        serdes_harness(
+            CanvasRecord::PaletteRemove(PaletteRemove {
+                offset: 16,
+                length: NonZeroU32::new(1).unwrap(),
+            }),
+            &[16],
+        );
+        // Long form
+        serdes_harness(
+            CanvasRecord::PaletteRemove(PaletteRemove {
+                offset: 16,
+                length: NonZeroU32::new(32).unwrap(),
+            }),
+            &[16, 32],
+        );
+    }
+
+    #[test]
+    fn codec_placement_delta() {
+        let serialiser = Serialiser::new();
+        let deserialiser = Serialiser::new();
+
+        serdes_harness_with(
+            &serialiser,
+            &deserialiser,
+            CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 1000,
+                pos: 300,
+                col: 5,
+            }),
+            &[
+                0xD0, 0x0F, // Time (+1000)
+                0xAC, 0x02, // Position
+                0x05, // Color
+            ],
+        );
+        serdes_harness_with(
+            &serialiser,
+            &deserialiser,
+            CanvasRecord::PlacementRemoveQuiet(PlacementRemove {
+                time: 1003,
+                pos: 21,
+            }),
+            &[
+                0x06, // Time (+3)
+                0x15, // Position
+            ],
+        );
+        // Time going backwards
+        serdes_harness_with(
+            &serialiser,
+            &deserialiser,
+            CanvasRecord::PlacementInsertFill(PlacementInsertFill {
+                time: 1001,
+                pos: (1, 2),
+                col: 3,
+            }),
+            &[
+                0x03, // Time (-2)
+                0x01, // Position 1
+                0x02, // Position 2
+                0x03, // Color
+            ],
+        );
+        serdes_harness_with(
+            &serialiser,
+            &deserialiser,
+            CanvasRecord::PlacementRemoveFill(PlacementRemoveFill {
+                time: 1001,
+                pos: (1, 2),
+            }),
+            &[
+                0x00, // Time (+0)
+                0x01, // Position 1
+                0x02, // Position 2
+            ],
+        );
+    }
+
+    #[test]
+    fn codec_placement_batch() {
+        serdes_harness(
+            CanvasRecord::PlacementInsertBatchQuiet(vec![
+                PlacementInsert {
+                    time: 10,
+                    pos: 1,
+                    col: 2,
+                },
+                PlacementInsert {
+                    time: 11,
+                    pos: 3,
+                    col: 4,
+                },
+            ]),
+            &[
+                0x14, 0x01, 0x02, // Placement 1 (+10)
+                0x02, 0x03, 0x04, // Placement 2 (+1)
+            ],
+        );
+        serdes_harness(
+            CanvasRecord::PlacementRemoveBatch(vec![
+                PlacementRemove { time: 10, pos: 1 },
+                PlacementRemove { time: 10, pos: 2 },
+            ]),
+            &[
+                0x14, 0x01, // Placement 1 (+10)
+                0x00, 0x02, // Placement 2 (+0)
+            ],
+        );
+
+        // A batch cut short by the writer does not advance time
+        let serialiser = Serialiser::new();
+        let batch = vec![
+            PlacementRemove { time: 10, pos: 1 },
+            PlacementRemove { time: 20, pos: 2 },
+        ];
+        let mut buf = [0; 3];
+        serialiser
+            .write_placement_remove_batch(&batch, buf.as_mut_slice())
+            .expect_err("succeeded write unexpectedly");
+        assert_eq!(serialiser.time(), 0);
+    }
+
+    #[test]
+    fn codec_placement_invalid() {
+        // Color exceeds u32
+        des_harness_err(
+            PLACEMENT_INSERT_TYPE_ID,
+            &[0x00, 0x00, 0x80, 0x80, 0x80, 0x80, 0x10],
+            Error::field("col", &(1u64 << 32).to_le_bytes()),
+        );
+        // Failed records do not advance time
+        let serialiser = Serialiser::new();
+        serialiser
+            .deserialise_record(PLACEMENT_INSERT_TYPE_ID, &[0x02, 0x00])
+            .expect_err("succeeded deserialise unexpectedly");
+        assert_eq!(serialiser.time(), 0);
+    }
+
+    #[test]
+    fn codec_identifier() {
+        serdes_harness(CanvasRecord::IdentifierNumeric(1234u64), &[0xD2, 0x09]);
+        serdes_harness(
+            CanvasRecord::IdentifierString("Etos2".to_string()),
+            b"Etos2",
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use std::{borrow::Borrow, num::NonZeroU32, ops::RangeInclusive};
+
+use crate::{
+    CanvasRecord,
+    render::{self, Image, Until, record_until},
+    state::{CanvasState, Error, change_times},
+};
+
+/// How counts map onto a [`ColorRamp`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Scale {
+    /// Proportional to the count.
+    #[default]
+    Linear,
+    /// Proportional to `ln(1 + count)`, so pixels placed a few times still stand out next to
+    /// the busiest.
+    Log,
+}
+
+/// Colors interpolated linearly between evenly spaced stops, from the least to the most placed
+/// pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorRamp {
+    stops: Vec<[u8; 4]>,
+}
+
+impl ColorRamp {
+    /// Ramp through `stops`, or `None` if there are none.
+    pub fn new(stops: Vec<[u8; 4]>) -> Option<Self> {
+        (!stops.is_empty()).then_some(Self { stops })
+    }
+
+    pub fn stops(&self) -> &[[u8; 4]] {
+        &self.stops
+    }
+
+    /// Color at `t`, clamped to between 0 and 1.
+    pub fn sample(&self, t: f64) -> [u8; 4] {
+        let t = t.clamp(0.0, 1.0) * (self.stops.len() - 1) as f64;
+        let i = (t as usize).min(self.stops.len() - 1);
+        let (from, to) = (self.stops[i], self.stops[(i + 1).min(self.stops.len() - 1)]);
+        let frac = t - i as f64;
+        std::array::from_fn(|c| {
+            (from[c] as f64 + (to[c] as f64 - from[c] as f64) * frac).round() as u8
+        })
+    }
+}
+
+impl Default for ColorRamp {
+    /// Dark blue through red and yellow to white.
+    fn default() -> Self {
+        Self {
+            stops: vec![
+                [0x10, 0x10, 0x60, 0xFF],
+                [0xD0, 0x20, 0x20, 0xFF],
+                [0xFF, 0xD0, 0x20, 0xFF],
+                [0xFF, 0xFF, 0xFF, 0xFF],
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatmapOptions {
+    pub scale: Scale,
+    pub ramp: ColorRamp,
+    /// Color of pixels that were never placed.
+    pub background: [u8; 4],
+    /// Width and height in pixels of each canvas pixel.
+    pub pixel_scale: NonZeroU32,
+}
+
+impl Default for HeatmapOptions {
+    fn default() -> Self {
+        Self {
+            scale: Scale::default(),
+            ramp: ColorRamp::default(),
+            background: [0x00, 0x00, 0x00, 0xFF],
+            pixel_scale: NonZeroU32::MIN,
+        }
+    }
+}
+
+/// Number of placements of every pixel within a time window.
+///
+/// Only placements count, including each pixel covered by a fill, while removals do not. Counts
+/// follow their pixels through a [`CanvasResize`](crate::CanvasResize) as in [`CanvasState`],
+/// and a [`CanvasMeta`](crate::CanvasMeta) starts again from zero.
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    window: RangeInclusive<u64>,
+    state: CanvasState,
+    counts: Vec<u64>,
+}
+
+impl Heatmap {
+    /// Count the placements at times within `window`.
+    pub fn new(window: RangeInclusive<u64>) -> Self {
+        Self {
+            window,
+            state: CanvasState::default(),
+            counts: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.state.size()
+    }
+
+    /// Count of every pixel in row-major order.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Highest count of any pixel.
+    pub fn max(&self) -> u64 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Apply a record, counting its placements within the window. On error nothing is changed.
+    pub fn push(&mut self, record: &CanvasRecord) -> Result<(), Error> {
+        let previous_size = self.state.size();
+        let changes = self.state.apply(record)?;
+
+        match record {
+            CanvasRecord::CanvasMeta(_) => {
+                self.counts = vec![0; self.state.len() as usize];
+            }
+            CanvasRecord::CanvasResize(rec) => {
+                let mut counts = vec![0; self.state.len() as usize];
+                for (pos, count) in self.counts.iter().enumerate() {
+                    if let Some(pos) = rec.remap_index(pos as u64, previous_size) {
+                        counts[pos as usize] = *count;
+                    }
+                }
+                self.counts = counts;
+            }
+            CanvasRecord::CanvasKeyframe(rec) if rec.size != previous_size => {
+                self.counts = vec![0; self.state.len() as usize];
+            }
+            _ => {
+                for (change, time) in changes.pixels.iter().zip(change_times(record)) {
+                    let in_window = time.is_some_and(|t| self.window.contains(&t));
+                    if change.current.is_some() && in_window {
+                        self.counts[change.pos as usize] += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Color every pixel by its count relative to [`max`](Self::max), or an error if the scaled
+    /// image is above [`MAX_PIXELS`](crate::position::MAX_PIXELS).
+    pub fn image(&self, options: &HeatmapOptions) -> Result<Image, render::Error> {
+        let (width, height) = self.size();
+        let max = self.max();
+        let normalise = |count: u64| match options.scale {
+            Scale::Linear => count as f64 / max as f64,
+            Scale::Log => (count as f64).ln_1p() / (max as f64).ln_1p(),
+        };
+
+        let data = self
+            .counts
+            .iter()
+            .flat_map(|&count| match count {
+                0 => options.background,
+                count => options.ramp.sample(normalise(count)),
+            })
+            .collect();
+
+        Image {
+            width,
+            height,
+            data,
+        }
+        .scaled(options.pixel_scale)
+    }
+}
+
+/// Count the placements of a stream at times within `window`.
+///
+/// As with [`render_at`](crate::render::render_at), records are replayed until the first
+/// placement after the window, so the stream must be sorted by time.
+pub fn heatmap<R: Borrow<CanvasRecord>>(
+    records: impl IntoIterator<Item = R>,
+    window: RangeInclusive<u64>,
+) -> Result<Heatmap, Error> {
+    let end = *window.end();
+    let mut heatmap = Heatmap::new(window);
+    for record in records {
+        let record = record.borrow();
+        match record_until(record, end) {
+            Until::Whole => heatmap.push(record)?,
+            Until::Partial(record) => {
+                heatmap.push(&record)?;
+                break;
+            }
+            Until::None => break,
+        }
+    }
+
+    Ok(heatmap)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CanvasMeta, PlacementInsert, PlacementInsertFill, PlacementRemove};
+
+    use super::*;
+
+    fn records() -> Vec<CanvasRecord> {
+        let place = |time, pos| PlacementInsert { time, pos, col: 0 };
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (2, 2),
+            }),
+            CanvasRecord::PlacementInsert(place(1, 0)),
+            // (0, 0) to (1, 0)
+            CanvasRecord::PlacementInsertFill(PlacementInsertFill {
+                time: 2,
+                pos: (0, 1),
+                col: 0,
+            }),
+            CanvasRecord::PlacementRemove(PlacementRemove { time: 3, pos: 1 }),
+            CanvasRecord::PlacementInsertBatch(vec![place(4, 0), place(5, 0), place(6, 3)]),
+        ]
+    }
+
+    #[test]
+    fn heatmap_counts() {
+        let all = heatmap(records(), 0..=u64::MAX).unwrap();
+        assert_eq!(all.counts(), &[4, 1, 0, 1]);
+        assert_eq!(all.max(), 4);
+
+        // Window within the batch
+        let window = heatmap(records(), 2..=5).unwrap();
+        assert_eq!(window.counts(), &[3, 1, 0, 0]);
+
+        assert!(
+            heatmap(records(), 10..=20)
+                .unwrap()
+                .counts()
+                .iter()
+                .all(|&c| c == 0)
+        );
+    }
+
+    #[test]
+    fn heatmap_image() {
+        let heatmap = heatmap(records(), 0..=u64::MAX).unwrap();
+        let ramp = ColorRamp::new(vec![[0x00, 0x00, 0x00, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF]]);
+        let mut options = HeatmapOptions {
+            ramp: ramp.unwrap(),
+            background: [0x00; 4],
+            ..HeatmapOptions::default()
+        };
+
+        let image = heatmap.image(&options).unwrap();
+        assert_eq!(image.pixel(0, 0), Some([0xFF; 4]));
+        assert_eq!(image.pixel(1, 0), Some([0x40, 0x40, 0x40, 0xFF]));
+        assert_eq!(image.pixel(0, 1), Some([0x00; 4]));
+
+        // ln(2) / ln(5) of the way
+        options.scale = Scale::Log;
+        let image = heatmap.image(&options).unwrap();
+        assert_eq!(image.pixel(1, 0), Some([0x6E, 0x6E, 0x6E, 0xFF]));
+        assert_eq!(image.pixel(0, 0), Some([0xFF; 4]));
+
+        // Nothing to draw before a meta
+        options.pixel_scale = NonZeroU32::new(2).unwrap();
+        let image = Heatmap::new(0..=u64::MAX).image(&options).unwrap();
+        assert_eq!((image.width, image.height), (0, 0));
+    }
+
+    #[test]
+    fn color_ramp() {
+        let ramp = ColorRamp::default();
+        assert_eq!(ramp.sample(0.0), ramp.stops()[0]);
+        assert_eq!(ramp.sample(1.0), [0xFF; 4]);
+        assert_eq!(ramp.sample(2.0), [0xFF; 4]);
+        assert_eq!(ramp.sample(0.5), [0xE8, 0x78, 0x20, 0xFF]);
+        assert!(ColorRamp::new(Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,571 @@
+//! Spatial index of the placements affecting each pixel.
+//!
+//! An index is stored as [`HISTORY_MAGIC`], the canvas width and height (`u32` each), the number
+//! of records indexed and of placements (`u64` each), followed by every [`Placement`] as its record
+//! index and time (`u64` each), the corners of its rectangle (`u32` each, x before y, `min` before
+//! `max`) and its colour plus one (`u64`, 0 for removals). All integers are little endian.
+
+use std::{
+    borrow::Borrow,
+    io::{Read, Write},
+    ops::RangeInclusive,
+};
+
+use msrf::error::IoError;
+
+use crate::{
+    CanvasRecord, CanvasResize, codec,
+    position::{self, Position, Rect},
+};
+
+pub use crate::position::Error;
+
+pub const HISTORY_MAGIC: [u8; 4] = *b"MCHI";
+
+// Fewest single pixel placements pushed before they are moved into the per pixel lists
+const COMPACT_MIN: usize = 4096;
+
+/// A placement or removal covering `rect`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// Index of the record in the stream, shared by every placement of a batch.
+    pub index: u64,
+    pub time: u64,
+    /// Pixels written, a single pixel unless the record is a fill.
+    pub rect: Rect,
+    /// Palette index placed, or `None` for removals.
+    pub col: Option<u32>,
+}
+
+/// Index from pixels to the placements and removals that wrote them, in stream order.
+///
+/// Single pixel placements are listed per pixel and fills are kept as rectangles, so a fill
+/// costs the same to index however large it is. The per pixel lists are rebuilt as placements
+/// are pushed, with those pushed since searched one by one, and only take space for pixels that
+/// were placed. Positions are those of the most recent canvas: a [`CanvasResize`] moves the
+/// history of every pixel with it and drops that of discarded pixels, while a
+/// [`CanvasMeta`](crate::CanvasMeta) (or a keyframe of another size) starts a new canvas with no
+/// history.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryIndex {
+    size: (u32, u32),
+    records: u64,
+    placements: Vec<Placement>,
+    // Pixels with single pixel placements among the first `indexed` placements, ascending, so
+    // that the indices into `placements` of those of `pixels[i]` are
+    // `ids[offsets[i]..offsets[i + 1]]` and the index costs nothing for untouched pixels
+    pixels: Vec<u64>,
+    offsets: Vec<usize>,
+    ids: Vec<usize>,
+    indexed: usize,
+    // Indices into `placements` of fills
+    fills: Vec<usize>,
+}
+
+impl HistoryIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index a stream of records from its start.
+    pub fn from_records<R: Borrow<CanvasRecord>>(
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<Self, Error> {
+        let mut index = Self::new();
+        for record in records {
+            index.push(record.borrow())?;
+        }
+
+        index.compact();
+        Ok(index)
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Number of records pushed, so the index of the next record.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Every indexed placement in stream order.
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    /// Index the next record of the stream.
+    ///
+    /// Records are checked against the canvas as in [`CanvasState::apply`], and on error the
+    /// index is left untouched.
+    ///
+    /// [`CanvasState::apply`]: crate::state::CanvasState::apply
+    pub fn push(&mut self, record: &CanvasRecord) -> Result<(), Error> {
+        let (index, size) = (self.records, self.size);
+        let pixel = |pos: u64| Position::from_index(pos, size).map(|pos| Rect::new(pos, pos));
+        let fill = |corners: (u64, u64)| Rect::from_fill(corners, size);
+
+        let placements = match record {
+            CanvasRecord::CanvasMeta(meta) => {
+                self.reset(meta.size)?;
+                Vec::new()
+            }
+            CanvasRecord::CanvasResize(rec) => {
+                self.resize(rec)?;
+                Vec::new()
+            }
+            CanvasRecord::CanvasKeyframe(rec) => {
+                if rec.size != self.size {
+                    self.reset(rec.size)?;
+                }
+                Vec::new()
+            }
+            CanvasRecord::PlacementInsert(rec) | CanvasRecord::PlacementInsertQuiet(rec) => {
+                vec![(rec.time, pixel(rec.pos)?, Some(rec.col))]
+            }
+            CanvasRecord::PlacementInsertFill(rec)
+            | CanvasRecord::PlacementInsertFillQuiet(rec) => {
+                vec![(rec.time, fill(rec.pos)?, Some(rec.col))]
+            }
+            CanvasRecord::PlacementRemove(rec) | CanvasRecord::PlacementRemoveQuiet(rec) => {
+                vec![(rec.time, pixel(rec.pos)?, None)]
+            }
+            CanvasRecord::PlacementRemoveFill(rec)
+            | CanvasRecord::PlacementRemoveFillQuiet(rec) => {
+                vec![(rec.time, fill(rec.pos)?, None)]
+            }
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => batch
+                .iter()
+                .map(|rec| Ok((rec.time, pixel(rec.pos)?, Some(rec.col))))
+                .collect::<Result<_, Error>>()?,
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => batch
+                .iter()
+                .map(|rec| Ok((rec.time, pixel(rec.pos)?, None)))
+                .collect::<Result<_, Error>>()?,
+            CanvasRecord::PaletteInsert(_)
+            | CanvasRecord::PaletteRemove(_)
+            | CanvasRecord::IdentifierNumeric(_)
+            | CanvasRecord::IdentifierString(_)
+            | CanvasRecord::IdentifierSecret(_) => Vec::new(),
+        };
+
+        for (time, rect, col) in placements {
+            self.insert(Placement {
+                index,
+                time,
+                rect,
+                col,
+            });
+        }
+
+        self.records += 1;
+        if self.placements.len() - self.indexed > self.indexed.max(COMPACT_MIN) {
+            self.compact();
+        }
+        Ok(())
+    }
+
+    /// Placements that wrote `pos` in stream order, empty if it is outside the canvas.
+    pub fn pixel(&self, pos: Position) -> Vec<&Placement> {
+        let Ok(i) = pos.to_index(self.size) else {
+            return Vec::new();
+        };
+
+        let mut ids = self.indexed(i..=i).to_vec();
+        ids.extend(self.unindexed(|rect| rect.contains(pos)));
+        ids.extend(
+            self.fills
+                .iter()
+                .filter(|&&id| self.placements[id].rect.contains(pos)),
+        );
+        self.resolve(ids)
+    }
+
+    /// Placements that wrote any pixel of `rect` in stream order, each listed once.
+    pub fn region(&self, rect: Rect) -> Vec<&Placement> {
+        let Some(rect) = Rect::canvas(self.size).and_then(|canvas| canvas.intersection(&rect))
+        else {
+            return Vec::new();
+        };
+
+        let width = self.size.0 as u64;
+        let mut ids: Vec<usize> = (rect.min.y..=rect.max.y)
+            .flat_map(|y| {
+                let row = y as u64 * width;
+                self.indexed(row + rect.min.x as u64..=row + rect.max.x as u64)
+            })
+            .copied()
+            .collect();
+        ids.extend(self.unindexed(|other| other.intersection(&rect).is_some()));
+        ids.extend(
+            self.fills
+                .iter()
+                .filter(|&&id| self.placements[id].rect.intersection(&rect).is_some()),
+        );
+        self.resolve(ids)
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(&HISTORY_MAGIC)?;
+        writer.write_all(&self.size.0.to_le_bytes())?;
+        writer.write_all(&self.size.1.to_le_bytes())?;
+        writer.write_all(&self.records.to_le_bytes())?;
+        writer.write_all(&(self.placements.len() as u64).to_le_bytes())?;
+        for placement in &self.placements {
+            let Rect { min, max } = placement.rect;
+            let col = placement.col.map_or(0, |col| col as u64 + 1);
+            writer.write_all(&placement.index.to_le_bytes())?;
+            writer.write_all(&placement.time.to_le_bytes())?;
+            for value in [min.x, min.y, max.x, max.y] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.write_all(&col.to_le_bytes())?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, IoError<codec::Error>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != HISTORY_MAGIC {
+            return Err(IoError::Parse(codec::Error::field("magic", &magic)));
+        }
+
+        let size = (read_u32(&mut reader)?, read_u32(&mut reader)?);
+        let mut index = Self::new();
+        index.reset(size).map_err(|_| {
+            let mut value = size.0.to_le_bytes().to_vec();
+            value.extend_from_slice(&size.1.to_le_bytes());
+            IoError::Parse(codec::Error::field("size", &value))
+        })?;
+        let records = read_u64(&mut reader)?;
+        let len = read_u64(&mut reader)?;
+
+        for _ in 0..len {
+            let (record, time) = (read_u64(&mut reader)?, read_u64(&mut reader)?);
+            let mut corners = [0; 4];
+            for corner in &mut corners {
+                *corner = read_u32(&mut reader)?;
+            }
+            let col = read_u64(&mut reader)?;
+
+            let [min_x, min_y, max_x, max_y] = corners;
+            let (min, max) = (Position::new(min_x, min_y), Position::new(max_x, max_y));
+            if min.x > max.x || min.y > max.y || !max.is_within(size) {
+                return Err(IoError::Parse(codec::Error::field(
+                    "rect",
+                    &corners_bytes(corners),
+                )));
+            }
+            if record >= records
+                || index
+                    .placements
+                    .last()
+                    .is_some_and(|last| record < last.index)
+            {
+                return Err(IoError::Parse(codec::Error::field(
+                    "index",
+                    &record.to_le_bytes(),
+                )));
+            }
+            let col = col
+                .checked_sub(1)
+                .map(u32::try_from)
+                .transpose()
+                .map_err(|_| IoError::Parse(codec::Error::field("col", &col.to_le_bytes())))?;
+
+            index.insert(Placement {
+                index: record,
+                time,
+                rect: Rect { min, max },
+                col,
+            });
+        }
+
+        index.records = records;
+        index.compact();
+        Ok(index)
+    }
+
+    // Start an empty canvas of `size`, or an error if it is too large to index
+    fn reset(&mut self, size: (u32, u32)) -> Result<(), Error> {
+        position::pixel_count(size)?;
+        *self = Self {
+            size,
+            records: self.records,
+            ..Self::default()
+        };
+        Ok(())
+    }
+
+    fn resize(&mut self, rec: &CanvasResize) -> Result<(), Error> {
+        // Checked before the placements are taken so they are kept on error
+        position::pixel_count(rec.size)?;
+        let placements = std::mem::take(&mut self.placements);
+        self.reset(rec.size)?;
+        for placement in placements {
+            if let Some(rect) = translate(placement.rect, rec) {
+                self.insert(Placement { rect, ..placement });
+            }
+        }
+        self.compact();
+        Ok(())
+    }
+
+    fn insert(&mut self, placement: Placement) {
+        if placement.rect.area() != 1 {
+            self.fills.push(self.placements.len());
+        }
+        self.placements.push(placement);
+    }
+
+    // Rebuild the per pixel lists from every single pixel placement
+    fn compact(&mut self) {
+        // Within the canvas, as only checked or clipped rectangles are inserted
+        let width = self.size.0 as u64;
+        let mut singles: Vec<(u64, usize)> = self
+            .placements
+            .iter()
+            .enumerate()
+            .filter(|(_, placement)| placement.rect.area() == 1)
+            .map(|(id, placement)| {
+                let pos = placement.rect.min;
+                (pos.y as u64 * width + pos.x as u64, id)
+            })
+            .collect();
+        // Stable, so each pixel keeps its placements in stream order
+        singles.sort_by_key(|&(pixel, _)| pixel);
+
+        self.pixels.clear();
+        self.offsets.clear();
+        self.ids.clear();
+        for (pixel, id) in singles {
+            if self.pixels.last() != Some(&pixel) {
+                self.pixels.push(pixel);
+                self.offsets.push(self.ids.len());
+            }
+            self.ids.push(id);
+        }
+        self.offsets.push(self.ids.len());
+        self.indexed = self.placements.len();
+    }
+
+    // Single pixel placements moved into the lists of the pixels in `range`
+    fn indexed(&self, range: RangeInclusive<u64>) -> &[usize] {
+        let start = self.pixels.partition_point(|pixel| pixel < range.start());
+        let end = self.pixels.partition_point(|pixel| pixel <= range.end());
+        match (self.offsets.get(start), self.offsets.get(end)) {
+            (Some(&start), Some(&end)) => &self.ids[start..end],
+            _ => &[],
+        }
+    }
+
+    // Single pixel placements pushed since the lists were rebuilt that `filter` accepts
+    fn unindexed(&self, filter: impl Fn(&Rect) -> bool) -> impl Iterator<Item = usize> {
+        self.placements[self.indexed..]
+            .iter()
+            .zip(self.indexed..)
+            .filter(move |(placement, _)| placement.rect.area() == 1 && filter(&placement.rect))
+            .map(|(_, id)| id)
+    }
+
+    fn resolve(&self, mut ids: Vec<usize>) -> Vec<&Placement> {
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter().map(|id| &self.placements[id]).collect()
+    }
+}
+
+// Part of `rect` still on the canvas after `rec`, in the new canvas's positions
+fn translate(rect: Rect, rec: &CanvasResize) -> Option<Rect> {
+    let shift = |pos: Position| {
+        Position::new(
+            pos.x.saturating_add(rec.offset.0),
+            pos.y.saturating_add(rec.offset.1),
+        )
+    };
+    let moved = Rect {
+        min: shift(rect.min),
+        max: shift(rect.max),
+    };
+    Rect::canvas(rec.size)?.intersection(&moved)
+}
+
+fn corners_bytes(corners: [u32; 4]) -> Vec<u8> {
+    corners
+        .iter()
+        .flat_map(|corner| corner.to_le_bytes())
+        .collect()
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CanvasMeta, PlacementInsert, PlacementInsertFill, PlacementRemove};
+
+    use super::*;
+
+    fn records() -> Vec<CanvasRecord> {
+        let place = |time, pos, col| PlacementInsert { time, pos, col };
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (4, 4),
+            }),
+            CanvasRecord::PlacementInsert(place(1, 5, 1)),
+            CanvasRecord::IdentifierNumeric(1),
+            // (1, 1) to (2, 2)
+            CanvasRecord::PlacementInsertFill(PlacementInsertFill {
+                time: 2,
+                pos: (5, 10),
+                col: 2,
+            }),
+            CanvasRecord::PlacementInsertBatch(vec![place(3, 0, 3), place(4, 5, 3)]),
+            CanvasRecord::PlacementRemove(PlacementRemove { time: 5, pos: 15 }),
+        ]
+    }
+
+    fn indices(placements: Vec<&Placement>) -> Vec<(u64, u64)> {
+        placements.iter().map(|p| (p.index, p.time)).collect()
+    }
+
+    #[test]
+    fn history_pixel() {
+        let index = HistoryIndex::from_records(records()).unwrap();
+        assert_eq!(index.records(), 6);
+        assert_eq!(index.placements().len(), 5);
+
+        let history = index.pixel(Position::new(1, 1));
+        assert_eq!(indices(history.clone()), vec![(1, 1), (3, 2), (4, 4)]);
+        assert_eq!(
+            history.iter().map(|p| p.col).collect::<Vec<_>>(),
+            vec![Some(1), Some(2), Some(3)]
+        );
+        assert_eq!(indices(index.pixel(Position::new(2, 2))), vec![(3, 2)]);
+        assert_eq!(index.pixel(Position::new(3, 3))[0].col, None);
+        assert!(index.pixel(Position::new(3, 0)).is_empty());
+        assert!(index.pixel(Position::new(4, 0)).is_empty());
+
+        // Each placement once, partly outside the canvas
+        let region = Rect::new(Position::new(0, 0), Position::new(9, 1));
+        assert_eq!(
+            indices(index.region(region)),
+            vec![(1, 1), (3, 2), (4, 3), (4, 4)]
+        );
+
+        let mut invalid = index.clone();
+        let out = CanvasRecord::PlacementRemove(PlacementRemove { time: 6, pos: 16 });
+        assert!(invalid.push(&out).is_err());
+        assert_eq!(invalid, index);
+    }
+
+    #[test]
+    fn history_resize() {
+        let mut records = records();
+        records.push(CanvasRecord::CanvasResize(CanvasResize {
+            time: 6,
+            size: (3, 3),
+            offset: (1, 0),
+        }));
+        let index = HistoryIndex::from_records(&records).unwrap();
+
+        // (1, 1) moved to (2, 1), the fill is clipped and (3, 3) discarded
+        assert_eq!(
+            indices(index.pixel(Position::new(2, 1))),
+            vec![(1, 1), (3, 2), (4, 4)]
+        );
+        assert_eq!(
+            index.placements()[1].rect,
+            Rect::new(Position::new(2, 1), Position::new(2, 2))
+        );
+        assert_eq!(index.placements().len(), 4);
+        assert!(index.pixel(Position::new(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn history_unindexed() {
+        let records = records();
+        let index = HistoryIndex::from_records(&records).unwrap();
+        let mut pushed = HistoryIndex::new();
+        for record in &records {
+            pushed.push(record).unwrap();
+        }
+
+        // Same placements whether or not they were moved into the per pixel lists
+        assert_ne!(pushed, index);
+        let region = Rect::new(Position::new(0, 0), Position::new(3, 3));
+        assert_eq!(
+            indices(pushed.region(region)),
+            indices(index.region(region))
+        );
+        for pos in region.iter() {
+            assert_eq!(indices(pushed.pixel(pos)), indices(index.pixel(pos)));
+        }
+
+        let mut too_large = pushed.clone();
+        let meta = CanvasRecord::CanvasMeta(CanvasMeta {
+            name: "test".to_string(),
+            platform: "pxls.space".to_string(),
+            time: 6,
+            size: (u32::MAX, u32::MAX),
+        });
+        assert!(too_large.push(&meta).is_err());
+        assert_eq!(too_large, pushed);
+    }
+
+    #[test]
+    fn history_archive() {
+        let index = HistoryIndex::from_records(records()).unwrap();
+        let mut buf = Vec::new();
+        index.write(&mut buf).unwrap();
+
+        assert_eq!(HistoryIndex::read(buf.as_slice()).unwrap(), index);
+        assert!(HistoryIndex::read(&buf[..buf.len() - 1]).is_err());
+
+        // A rectangle outside the canvas, after the 28 byte header and 16 bytes of the first entry
+        let mut corrupt = buf.clone();
+        corrupt[52..56].copy_from_slice(&4u32.to_le_bytes());
+        assert!(matches!(
+            HistoryIndex::read(corrupt.as_slice()),
+            Err(IoError::Parse(codec::Error::InvalidField {
+                field: "rect",
+                ..
+            }))
+        ));
+
+        // The largest canvas costs nothing until it is placed on
+        let mut header = buf[..28].to_vec();
+        header[4..12].copy_from_slice(&[0x00, 0x40, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00]);
+        header[20..28].fill(0);
+        let empty = HistoryIndex::read(header.as_slice()).unwrap();
+        assert_eq!(empty.size(), (1 << 14, 1 << 14));
+        assert!(empty.region(Rect::canvas(empty.size()).unwrap()).is_empty());
+
+        // A canvas too large to index
+        let mut corrupt = buf;
+        corrupt[4..12].copy_from_slice(&[0xFF; 8]);
+        assert!(matches!(
+            HistoryIndex::read(corrupt.as_slice()),
+            Err(IoError::Parse(codec::Error::InvalidField {
+                field: "size",
+                ..
+            }))
+        ));
+    }
+}
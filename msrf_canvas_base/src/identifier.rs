@@ -0,0 +1,292 @@
+//! Interning of identifiers into dense handles.
+//!
+//! A table is stored as [`TABLE_MAGIC`], the number of anonymous handles handed out and of
+//! identifiers (`u64` each), followed by every identifier in handle order as its record type id
+//! (`u16`), value length (`u32`) and value encoded with the [`v0_0`](codec::v0_0) codec. All
+//! integers are little endian.
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use msrf::error::IoError;
+
+use crate::{
+    CanvasRecord, CanvasRecordRef, Identifier, MetaIdIndex,
+    codec::{self, RawSerialiser, v0_0},
+};
+
+pub const TABLE_MAGIC: [u8; 4] = *b"MCIT";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    TableFull,
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TableFull => write!(
+                f,
+                "identifier table is full ({} handles)",
+                MetaIdIndex::MAX_INDEX + 1
+            ),
+        }
+    }
+}
+
+/// Interning table mapping each distinct [`Identifier`] to a dense [`MetaIdIndex`].
+///
+/// Interned handles are never unique and index the table in insertion order. Unique handles
+/// from [`anonymous`](Self::anonymous) are numbered separately and resolve to no identifier.
+///
+/// The table is stored apart from the archive (see the [module](self) documentation), as
+/// identifier records in an archive would set the author of the placements after them.
+/// [`from_records`](Self::from_records) instead rebuilds the table of the authors of an archive
+/// in order of first appearance.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IdentifierTable {
+    identifiers: Vec<Identifier>,
+    indices: HashMap<Identifier, MetaIdIndex>,
+    anonymous: usize,
+}
+
+impl IdentifierTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of interned identifiers.
+    pub fn len(&self) -> usize {
+        self.identifiers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.identifiers.is_empty()
+    }
+
+    /// Handle of `id`, interning it if it is not yet in the table.
+    pub fn intern(&mut self, id: &Identifier) -> Result<MetaIdIndex, Error> {
+        if let Some(index) = self.indices.get(id) {
+            return Ok(*index);
+        }
+
+        let index = MetaIdIndex::new(self.identifiers.len(), false).ok_or(Error::TableFull)?;
+        self.identifiers.push(id.clone());
+        self.indices.insert(id.clone(), index);
+        Ok(index)
+    }
+
+    /// New unique handle for an author without an identifier.
+    pub fn anonymous(&mut self) -> Result<MetaIdIndex, Error> {
+        let index = MetaIdIndex::new(self.anonymous, true).ok_or(Error::TableFull)?;
+        self.anonymous += 1;
+        Ok(index)
+    }
+
+    /// Handle of `id` if it has been interned.
+    pub fn index_of(&self, id: &Identifier) -> Option<MetaIdIndex> {
+        self.indices.get(id).copied()
+    }
+
+    /// Identifier of an interned handle, `None` for unique handles and [`MetaIdIndex::NONE`].
+    pub fn get(&self, index: MetaIdIndex) -> Option<&Identifier> {
+        if index.is_unique() || index.is_none() {
+            return None;
+        }
+
+        self.identifiers.get(index.into_index())
+    }
+
+    /// Interned identifiers in handle order.
+    pub fn iter(&self) -> impl Iterator<Item = &Identifier> {
+        self.identifiers.iter()
+    }
+
+    /// Load a table by interning the identifier of every identifier record, skipping others.
+    pub fn from_records<R: Borrow<CanvasRecord>>(
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<Self, Error> {
+        let mut table = Self::new();
+        for record in records {
+            if let Some(id) = record.borrow().identifier() {
+                table.intern(&id)?;
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), IoError<codec::Error>> {
+        writer.write_all(&TABLE_MAGIC)?;
+        writer.write_all(&(self.anonymous as u64).to_le_bytes())?;
+        writer.write_all(&(self.identifiers.len() as u64).to_le_bytes())?;
+        for id in &self.identifiers {
+            let record = CanvasRecord::from(id.clone());
+            let len = v0_0::Serialiser
+                .encoded_len(&record)
+                .map_err(IoError::Parse)?;
+            let len = u32::try_from(len).map_err(|_| {
+                IoError::Parse(codec::Error::field("length", &(len as u64).to_le_bytes()))
+            })?;
+
+            writer.write_all(&record.raw_id().to_le_bytes())?;
+            writer.write_all(&len.to_le_bytes())?;
+            v0_0::Serialiser.write_record(&record, &mut writer)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, IoError<codec::Error>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != TABLE_MAGIC {
+            return Err(IoError::Parse(codec::Error::field("magic", &magic)));
+        }
+
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let anonymous = usize::try_from(u64::from_le_bytes(buf))
+            .ok()
+            .filter(|anonymous| *anonymous <= MetaIdIndex::MAX_INDEX + 1)
+            .ok_or_else(|| IoError::Parse(codec::Error::field("anonymous", &buf)))?;
+        reader.read_exact(&mut buf)?;
+        let count = u64::from_le_bytes(buf);
+
+        let mut table = Self {
+            anonymous,
+            ..Self::new()
+        };
+        let mut value = Vec::new();
+        for _ in 0..count {
+            let mut header = [0; 6];
+            reader.read_exact(&mut header)?;
+            let type_id = u16::from_le_bytes([header[0], header[1]]);
+            let len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]);
+
+            // Only allocate as much as the reader holds
+            value.clear();
+            (&mut reader).take(len as u64).read_to_end(&mut value)?;
+            if value.len() < len as usize {
+                return Err(IoError::Parse(codec::Error::length("value", value.len())(
+                    len as usize,
+                )));
+            }
+
+            let id = v0_0::Serialiser
+                .deserialise_record_ref(type_id, &value)
+                .map(CanvasRecordRef::into_owned)
+                .map_err(IoError::Parse)?
+                .identifier()
+                .ok_or_else(|| {
+                    IoError::Parse(codec::Error::field("type_id", &type_id.to_le_bytes()))
+                })?;
+
+            // Every identifier must get the next handle
+            let expected = table.len();
+            let index = table
+                .intern(&id)
+                .map_err(|_| IoError::Parse(codec::Error::field("count", &count.to_le_bytes())))?;
+            if index.into_index() != expected {
+                return Err(IoError::Parse(codec::Error::field("identifier", &value)));
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::PlacementInsert;
+
+    use super::*;
+
+    #[test]
+    fn identifier_table() {
+        let user = Identifier::String("user".to_string());
+        let secret = Identifier::Secret(vec![0xAB, 0x01]);
+
+        let mut table = IdentifierTable::new();
+        let a = table.intern(&user).unwrap();
+        let b = table.intern(&secret).unwrap();
+        assert_eq!(table.intern(&user).unwrap(), a);
+        assert_eq!((a.into_index(), b.into_index()), (0, 1));
+        assert!(!a.is_unique());
+
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(b), Some(&secret));
+        assert_eq!(table.index_of(&user), Some(a));
+        assert_eq!(table.index_of(&Identifier::Numerical(1)), None);
+
+        let anon = table.anonymous().unwrap();
+        assert!(anon.is_unique());
+        assert_ne!(table.anonymous().unwrap(), anon);
+        assert_eq!(table.get(anon), None);
+        assert_eq!(table.get(MetaIdIndex::NONE), None);
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn identifier_table_archive() {
+        let mut table = IdentifierTable::new();
+        for id in [
+            Identifier::Numerical(7),
+            Identifier::String("user".to_string()),
+            Identifier::Secret(vec![0xCD]),
+        ] {
+            table.intern(&id).unwrap();
+        }
+
+        let anon = table.anonymous().unwrap();
+
+        let mut buf = Vec::new();
+        table.write(&mut buf).unwrap();
+        let mut read = IdentifierTable::read(buf.as_slice()).unwrap();
+        assert_eq!(read, table);
+        // Anonymous handles continue after those handed out before writing
+        assert_ne!(read.anonymous().unwrap(), anon);
+
+        let mut truncated = buf.clone();
+        truncated.pop();
+        assert!(IdentifierTable::read(truncated.as_slice()).is_err());
+
+        // The same identifier twice would shift every following handle
+        let mut duplicate = IdentifierTable::new();
+        duplicate.intern(&Identifier::Numerical(7)).unwrap();
+        let mut buf = Vec::new();
+        duplicate.write(&mut buf).unwrap();
+        let entry = buf[20..].to_vec();
+        buf[12..20].copy_from_slice(&2u64.to_le_bytes());
+        buf.extend(entry);
+        assert!(matches!(
+            IdentifierTable::read(buf.as_slice()),
+            Err(IoError::Parse(codec::Error::InvalidField {
+                field: "identifier",
+                ..
+            }))
+        ));
+
+        // Rebuilt from the authors of a stream, skipping repeats and other records
+        let records = [
+            CanvasRecord::IdentifierNumeric(7),
+            CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 0,
+                pos: 0,
+                col: 0,
+            }),
+            CanvasRecord::IdentifierString("user".to_string()),
+            CanvasRecord::IdentifierNumeric(7),
+            CanvasRecord::IdentifierSecret(vec![0xCD]),
+        ];
+        let rebuilt = IdentifierTable::from_records(&records).unwrap();
+        assert!(rebuilt.iter().eq(table.iter()));
+    }
+}
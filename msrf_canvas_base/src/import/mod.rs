@@ -0,0 +1,210 @@
+//! Conversion of placement logs published by canvas platforms into records.
+//!
+//! Times are converted to milliseconds since the Unix epoch.
+
+use std::fmt::Display;
+
+use crate::position;
+
+pub mod pxls;
+pub mod reddit;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    MissingField {
+        line: u64,
+        field: &'static str,
+    },
+    InvalidField {
+        line: u64,
+        field: &'static str,
+        value: String,
+    },
+    OutOfBounds {
+        line: u64,
+        source: position::Error,
+    },
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::OutOfBounds { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::MissingField { line, field } => write!(f, "line {line}: missing `{field}`"),
+            Error::InvalidField { line, field, value } => {
+                write!(f, "line {line}: invalid `{field}` ({value:?})")
+            }
+            Error::OutOfBounds { line, source } => write!(f, "line {line}: {source}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+/// Parse `YYYY-MM-DD HH:MM:SS[.fff]` (UTC) into milliseconds since the Unix epoch.
+///
+/// The fraction may be separated by `.` or `,` and a trailing ` UTC` is ignored. Years from 1970
+/// to 9999 are accepted.
+pub fn parse_datetime(s: &str) -> Option<u64> {
+    let s = s.trim().trim_end_matches(" UTC");
+    let (date, time) = s.split_once([' ', 'T'])?;
+
+    let mut date = date.splitn(3, '-').map(|v| v.parse::<u64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if !(1970..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+    {
+        return None;
+    }
+
+    let (time, fraction) = match time.split_once(['.', ',']) {
+        Some((time, fraction)) => (time, fraction),
+        None => (time, ""),
+    };
+    let mut time = time.splitn(3, ':').map(|v| v.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Keep millisecond precision, padding or truncating the fraction
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let millis = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(3)
+        .fold(0, |acc, b| acc * 10 + (b - b'0') as u64);
+
+    let days = days_from_civil(year, month, day);
+    Some(((days * 24 + hour) * 60 + minute) * 60_000 + second * 1000 + millis)
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Decode a hex string, accepting either case.
+pub(crate) fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decode standard base64, with or without padding.
+pub(crate) fn parse_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for b in s.bytes() {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = acc << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    // A single leftover character cannot encode a byte
+    (bits < 6).then_some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn import_datetime() {
+        assert_eq!(parse_datetime("1970-01-01 00:00:00"), Some(0));
+        assert_eq!(
+            parse_datetime("2021-04-09 18:39:07,363"),
+            Some(1_617_993_547_363)
+        );
+        assert_eq!(
+            parse_datetime("2022-04-04 00:53:51.577 UTC"),
+            Some(1_649_033_631_577)
+        );
+        assert_eq!(
+            parse_datetime("2017-03-31 00:44:55.4 UTC"),
+            Some(1_490_921_095_400)
+        );
+        assert_eq!(parse_datetime("2000-02-29 12:00:00"), Some(951_825_600_000));
+        assert_eq!(parse_datetime("2021-13-01 00:00:00"), None);
+        assert_eq!(parse_datetime("2022-02-31 00:00:00"), None);
+        assert_eq!(parse_datetime("2100-02-29 00:00:00"), None);
+        assert_eq!(parse_datetime("2021-04-31 00:00:00"), None);
+        assert_eq!(
+            parse_datetime("9999-12-31 23:59:59"),
+            Some(253_402_300_799_000)
+        );
+        assert_eq!(parse_datetime("18446744073709551615-01-01 00:00:00"), None);
+        assert_eq!(parse_datetime("2021-01-01"), None);
+        assert_eq!(parse_datetime("2021-01-01 00:00:00.1a"), None);
+    }
+
+    #[test]
+    fn import_hex() {
+        assert_eq!(parse_hex("00fFa0"), Some(vec![0x00, 0xFF, 0xA0]));
+        assert_eq!(parse_hex(""), Some(vec![]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("zz"), None);
+    }
+
+    #[test]
+    fn import_base64() {
+        assert_eq!(parse_base64("aGVsbG8="), Some(b"hello".to_vec()));
+        assert_eq!(parse_base64("aGVsbG8"), Some(b"hello".to_vec()));
+        assert_eq!(parse_base64("/+8="), Some(vec![0xFF, 0xEF]));
+        assert_eq!(parse_base64("a"), None);
+        assert_eq!(parse_base64("a-b="), None);
+    }
+}
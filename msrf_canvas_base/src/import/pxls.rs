@@ -0,0 +1,359 @@
+//! Importer for pxls.space canvas logs.
+//!
+//! Each line holds tab separated `date`, `hash`, `x`, `y`, `color` and `action` fields, where
+//! `hash` is the hex encoded user hash and `color` is an index into the canvas palette, or `-1`
+//! for a transparent pixel.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, Lines},
+};
+
+use crate::{
+    CanvasMeta, CanvasRecord, PaletteInsert, PlacementInsert, PlacementRemove, position::Position,
+};
+
+use super::{Error, parse_datetime, parse_hex};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Place,
+    Undo,
+    Overwrite,
+    Rollback,
+    RollbackUndo,
+    Nuke,
+}
+
+impl Action {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "user place" => Some(Action::Place),
+            "user undo" => Some(Action::Undo),
+            "mod overwrite" => Some(Action::Overwrite),
+            "rollback" => Some(Action::Rollback),
+            "rollback undo" => Some(Action::RollbackUndo),
+            "console nuke" => Some(Action::Nuke),
+            _ => None,
+        }
+    }
+
+    /// Whether the action was taken by the user themselves rather than by moderation.
+    pub fn is_user(&self) -> bool {
+        matches!(self, Action::Place | Action::Undo)
+    }
+}
+
+/// A parsed log line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub time: u64,
+    pub hash: Vec<u8>,
+    pub pos: Position,
+    /// Palette index, `None` for a transparent pixel.
+    pub col: Option<u32>,
+    pub action: Action,
+}
+
+impl Entry {
+    pub fn parse(s: &str, line: u64) -> Result<Self, Error> {
+        let mut fields = s.split('\t');
+        let mut next = |field| fields.next().ok_or(Error::MissingField { line, field });
+        let invalid = |field, value: &str| Error::InvalidField {
+            line,
+            field,
+            value: value.to_string(),
+        };
+
+        let date = next("date")?;
+        let hash = next("hash")?;
+        let x = next("x")?;
+        let y = next("y")?;
+        let color = next("color")?;
+        let action = next("action")?;
+
+        Ok(Self {
+            time: parse_datetime(date).ok_or_else(|| invalid("date", date))?,
+            hash: parse_hex(hash).ok_or_else(|| invalid("hash", hash))?,
+            pos: Position::new(
+                x.parse().map_err(|_| invalid("x", x))?,
+                y.parse().map_err(|_| invalid("y", y))?,
+            ),
+            col: match color.parse::<i64>().map(u32::try_from) {
+                Ok(Ok(col)) => Some(col),
+                Ok(Err(_)) if color == "-1" => None,
+                _ => return Err(invalid("color", color)),
+            },
+            action: Action::parse(action.trim_end()).ok_or_else(|| invalid("action", action))?,
+        })
+    }
+}
+
+/// Iterator converting a pxls.space log into records.
+///
+/// The archive starts with `meta` and the palette as a single [`PaletteInsert`]. Each entry is
+/// preceded by an [`IdentifierSecret`](CanvasRecord::IdentifierSecret) holding its user hash
+/// whenever the hash differs from the previous entry's. Entries without a hash (console actions)
+/// have no identifier, so they keep the author of the entry before them.
+///
+/// User placements become [`PlacementInsert`] and moderation actions (overwrites, rollbacks and
+/// nukes) [`PlacementInsertQuiet`](CanvasRecord::PlacementInsertQuiet). A user undo restores the
+/// color the pixel had before the placement it undoes, or the logged color for a pixel not
+/// placed earlier in the log. Transparent pixels, and undos restoring an empty pixel, are
+/// written as removals.
+#[derive(Debug)]
+pub struct Importer<R> {
+    lines: Lines<R>,
+    size: (u32, u32),
+    line: u64,
+    hash: Option<Vec<u8>>,
+    // Current and previous color of every pixel placed so far, to restore on undo
+    pixels: HashMap<u64, (Option<u32>, Option<u32>)>,
+    pending: VecDeque<CanvasRecord>,
+}
+
+impl<R: BufRead> Importer<R> {
+    pub fn new(reader: R, meta: CanvasMeta, palette: Vec<[u8; 4]>) -> Self {
+        let size = meta.size;
+        let pending = VecDeque::from([
+            CanvasRecord::CanvasMeta(meta),
+            CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: 0,
+                colors: palette,
+            }),
+        ]);
+
+        Self {
+            lines: reader.lines(),
+            size,
+            line: 0,
+            hash: None,
+            pixels: HashMap::new(),
+            pending,
+        }
+    }
+
+    fn push_entry(&mut self, entry: Entry) -> Result<(), Error> {
+        let line = self.line;
+        let pos = entry
+            .pos
+            .to_index(self.size)
+            .map_err(|source| Error::OutOfBounds { line, source })?;
+
+        if entry.hash.is_empty() {
+            // Identify the next hashed entry even if it repeats the last hash
+            self.hash = None;
+        } else if self.hash.as_ref() != Some(&entry.hash) {
+            self.hash = Some(entry.hash.clone());
+            self.pending
+                .push_back(CanvasRecord::IdentifierSecret(entry.hash));
+        }
+
+        let col = match entry.action {
+            Action::Undo => {
+                let col = self.pixels.get(&pos).map_or(entry.col, |pixel| pixel.1);
+                self.pixels.insert(pos, (col, col));
+                col
+            }
+            _ => {
+                let previous = self.pixels.get(&pos).and_then(|pixel| pixel.0);
+                self.pixels.insert(pos, (entry.col, previous));
+                entry.col
+            }
+        };
+
+        let (time, quiet) = (entry.time, !entry.action.is_user());
+        let record = match col {
+            None => {
+                let rec = PlacementRemove { time, pos };
+                if quiet {
+                    CanvasRecord::PlacementRemoveQuiet(rec)
+                } else {
+                    CanvasRecord::PlacementRemove(rec)
+                }
+            }
+            Some(col) => {
+                let rec = PlacementInsert { time, pos, col };
+                if quiet {
+                    CanvasRecord::PlacementInsertQuiet(rec)
+                } else {
+                    CanvasRecord::PlacementInsert(rec)
+                }
+            }
+        };
+
+        self.pending.push_back(record);
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for Importer<R> {
+    type Item = Result<CanvasRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Err(e) = Entry::parse(&line, self.line).and_then(|e| self.push_entry(e)) {
+                return Some(Err(e));
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LOG: &str = "\
+2021-04-09 18:39:07,363\tab01\t1\t0\t2\tuser place
+2021-04-09 18:39:08,000\tab01\t1\t0\t0\tuser undo
+
+2021-04-09 18:40:00,500\tcd02\t0\t1\t1\tuser place
+2021-04-09 18:41:00,000\tcd02\t0\t1\t-1\tmod overwrite
+2021-04-09 18:42:00,000\t\t1\t1\t3\tconsole nuke
+";
+
+    fn meta() -> CanvasMeta {
+        CanvasMeta {
+            name: "c1".to_string(),
+            platform: "pxls.space".to_string(),
+            time: 1_617_993_540_000,
+            size: (2, 2),
+        }
+    }
+
+    #[test]
+    fn pxls_import() {
+        let palette = vec![
+            [0xFF; 4],
+            [0x00, 0x00, 0x00, 0xFF],
+            [0xFF, 0, 0, 0xFF],
+            [0; 4],
+        ];
+        let records: Vec<CanvasRecord> = Importer::new(LOG.as_bytes(), meta(), palette.clone())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let t0 = 1_617_993_547_363;
+        assert_eq!(
+            records,
+            vec![
+                CanvasRecord::CanvasMeta(meta()),
+                CanvasRecord::PaletteInsert(PaletteInsert {
+                    offset: 0,
+                    colors: palette
+                }),
+                CanvasRecord::IdentifierSecret(vec![0xAB, 0x01]),
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0,
+                    pos: 1,
+                    col: 2
+                }),
+                CanvasRecord::PlacementRemove(PlacementRemove {
+                    time: t0 + 637,
+                    pos: 1
+                }),
+                CanvasRecord::IdentifierSecret(vec![0xCD, 0x02]),
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0 + 53_137,
+                    pos: 2,
+                    col: 1
+                }),
+                CanvasRecord::PlacementRemoveQuiet(PlacementRemove {
+                    time: t0 + 112_637,
+                    pos: 2
+                }),
+                CanvasRecord::PlacementInsertQuiet(PlacementInsert {
+                    time: t0 + 172_637,
+                    pos: 3,
+                    col: 3
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn pxls_import_undo() {
+        const LOG: &str = "\
+2021-04-09 18:39:07,363\tab01\t1\t0\t2\tuser place
+2021-04-09 18:39:08,000\tcd02\t1\t0\t3\tuser place
+2021-04-09 18:39:09,000\tcd02\t1\t0\t0\tuser undo
+2021-04-09 18:39:10,000\tcd02\t0\t0\t1\tuser undo
+";
+        let records: Vec<CanvasRecord> = Importer::new(LOG.as_bytes(), meta(), Vec::new())
+            .skip(2)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let t0 = 1_617_993_547_363;
+        assert_eq!(
+            records,
+            vec![
+                CanvasRecord::IdentifierSecret(vec![0xAB, 0x01]),
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0,
+                    pos: 1,
+                    col: 2
+                }),
+                CanvasRecord::IdentifierSecret(vec![0xCD, 0x02]),
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0 + 637,
+                    pos: 1,
+                    col: 3
+                }),
+                // Restores the placement before
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0 + 1637,
+                    pos: 1,
+                    col: 2
+                }),
+                // Not placed earlier in the log, so falls back to the logged color
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0 + 2637,
+                    pos: 0,
+                    col: 1
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn pxls_import_errors() {
+        let import = |log: &'static str| {
+            Importer::new(log.as_bytes(), meta(), Vec::new())
+                .find_map(Result::err)
+                .expect("imported invalid log")
+        };
+
+        assert!(matches!(
+            import("2021-04-09 18:39:07,363\tab01\t5\t0\t2\tuser place"),
+            Error::OutOfBounds { line: 1, .. }
+        ));
+        assert!(matches!(
+            import("\n2021-04-09 18:39:07,363\tab01\t1\t0\t2\tuser dance"),
+            Error::InvalidField {
+                line: 2,
+                field: "action",
+                ..
+            }
+        ));
+        assert!(matches!(
+            import("2021-04-09 18:39:07,363\tab01\t1\t0"),
+            Error::MissingField {
+                line: 1,
+                field: "color"
+            }
+        ));
+    }
+}
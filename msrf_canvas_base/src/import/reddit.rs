@@ -0,0 +1,601 @@
+//! Importer for the r/place datasets published by Reddit.
+//!
+//! Columns are located by the header row, which covers every published year:
+//!
+//! - 2017: `ts,user_hash,x_coordinate,y_coordinate,color` with `color` a palette index.
+//! - 2022: `timestamp,user_id,pixel_color,coordinate` with `color` as `#RRGGBB`.
+//! - 2023: `timestamp,user,coordinate,pixel_color`, where coordinates are centred on the
+//!   canvas and may be negative.
+//!
+//! A `coordinate` is either a pixel `x,y`, a moderation rectangle `x1,y1,x2,y2` or, in 2023, a
+//! moderation circle `{X: x, Y: y, R: r}`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, Lines},
+};
+
+use crate::{
+    CanvasMeta, CanvasRecord, PaletteInsert, PlacementInsert, PlacementInsertFill,
+    palette::MAX_COLORS, position::Position,
+};
+
+use super::{Error, parse_base64, parse_datetime, parse_hex};
+
+/// Palette indexed by the `color` column of the 2017 dataset.
+pub const PALETTE_2017: [[u8; 4]; 16] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xE4, 0xE4, 0xE4, 0xFF],
+    [0x88, 0x88, 0x88, 0xFF],
+    [0x22, 0x22, 0x22, 0xFF],
+    [0xFF, 0xA7, 0xD1, 0xFF],
+    [0xE5, 0x00, 0x00, 0xFF],
+    [0xE5, 0x95, 0x00, 0xFF],
+    [0xA0, 0x6A, 0x42, 0xFF],
+    [0xE5, 0xD9, 0x00, 0xFF],
+    [0x94, 0xE0, 0x44, 0xFF],
+    [0x02, 0xBE, 0x01, 0xFF],
+    [0x00, 0xD3, 0xDD, 0xFF],
+    [0x00, 0x83, 0xC7, 0xFF],
+    [0x00, 0x00, 0xEA, 0xFF],
+    [0xCF, 0x6E, 0xE4, 0xFF],
+    [0x82, 0x00, 0x80, 0xFF],
+];
+
+/// Area painted by a row, in dataset coordinates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Shape {
+    Pixel { x: i64, y: i64 },
+    Rect { x1: i64, y1: i64, x2: i64, y2: i64 },
+    Circle { x: i64, y: i64, r: i64 },
+}
+
+impl Shape {
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(circle) = s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            let mut fields = circle.split(',').map(|field| {
+                let (key, value) = field.split_once(':')?;
+                Some((key.trim(), value.trim().parse::<i64>().ok()?))
+            });
+            return match (fields.next()??, fields.next()??, fields.next()??) {
+                (("X", x), ("Y", y), ("R", r)) if r >= 0 => Some(Shape::Circle { x, y, r }),
+                _ => None,
+            };
+        }
+
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<i64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        match values[..] {
+            [x, y] => Some(Shape::Pixel { x, y }),
+            [x1, y1, x2, y2] => Some(Shape::Rect { x1, y1, x2, y2 }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Coordinate {
+    Joined(usize),
+    Split(usize, usize),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Columns {
+    time: usize,
+    user: usize,
+    color: usize,
+    coordinate: Coordinate,
+}
+
+impl Columns {
+    fn parse(header: &str) -> Result<Self, Error> {
+        let names = split_csv(header);
+        let find = |field: &'static str, aliases: &[&str]| {
+            names
+                .iter()
+                .position(|name| aliases.contains(&name.trim()))
+                .ok_or(Error::MissingField { line: 1, field })
+        };
+
+        let coordinate = match find("coordinate", &["coordinate"]) {
+            Ok(i) => Coordinate::Joined(i),
+            Err(_) => Coordinate::Split(
+                find("x_coordinate", &["x_coordinate"])?,
+                find("y_coordinate", &["y_coordinate"])?,
+            ),
+        };
+
+        Ok(Self {
+            time: find("timestamp", &["ts", "timestamp"])?,
+            user: find("user", &["user_hash", "user_id", "user"])?,
+            color: find("color", &["color", "pixel_color"])?,
+            coordinate,
+        })
+    }
+}
+
+/// Iterator converting an r/place dataset into records.
+///
+/// The archive starts with `meta`. Colors are added to the palette the first time they are
+/// used, as a [`PaletteInsert`] of one color before the placement. Each row is preceded by its
+/// user id whenever it differs from the previous row's. If the first user id is base64, every id
+/// is written as an [`IdentifierSecret`](CanvasRecord::IdentifierSecret) of the decoded hash and
+/// an id that is not base64 is an error; otherwise every id is written as an
+/// [`IdentifierString`](CanvasRecord::IdentifierString).
+///
+/// `origin` is the dataset coordinate of the top left pixel of the canvas. Pixels become
+/// [`PlacementInsert`], moderation rectangles
+/// [`PlacementInsertFillQuiet`](CanvasRecord::PlacementInsertFillQuiet) and moderation circles a
+/// [`PlacementInsertBatchQuiet`](CanvasRecord::PlacementInsertBatchQuiet) of every pixel within
+/// them. Circles are clipped to the canvas, and a circle entirely outside it adds no records.
+/// Records keep the order of the rows.
+#[derive(Debug)]
+pub struct Importer<R> {
+    lines: Lines<R>,
+    size: (u32, u32),
+    origin: (i64, i64),
+    columns: Option<Columns>,
+    line: u64,
+    user: Option<String>,
+    // Whether user ids are base64 hashes, decided by the first row
+    secret_users: Option<bool>,
+    colors: HashMap<[u8; 4], u32>,
+    pending: VecDeque<CanvasRecord>,
+}
+
+impl<R: BufRead> Importer<R> {
+    pub fn new(reader: R, meta: CanvasMeta, origin: (i64, i64)) -> Self {
+        Self {
+            lines: reader.lines(),
+            size: meta.size,
+            origin,
+            columns: None,
+            line: 0,
+            user: None,
+            secret_users: None,
+            colors: HashMap::new(),
+            pending: VecDeque::from([CanvasRecord::CanvasMeta(meta)]),
+        }
+    }
+
+    fn push_row(&mut self, row: &str, columns: Columns) -> Result<(), Error> {
+        let line = self.line;
+        let fields = split_csv(row);
+        let field = |i: usize, field: &'static str| {
+            fields
+                .get(i)
+                .map(|s| s.trim())
+                .ok_or(Error::MissingField { line, field })
+        };
+        let invalid = |field, value: &str| Error::InvalidField {
+            line,
+            field,
+            value: value.to_string(),
+        };
+
+        let time = field(columns.time, "timestamp")?;
+        let time = parse_datetime(time).ok_or_else(|| invalid("timestamp", time))?;
+        let user = field(columns.user, "user")?;
+        let color_field = field(columns.color, "color")?;
+        let color = parse_color(color_field).ok_or_else(|| invalid("color", color_field))?;
+        let shape = match columns.coordinate {
+            Coordinate::Joined(i) => {
+                let coordinate = field(i, "coordinate")?;
+                Shape::parse(coordinate).ok_or_else(|| invalid("coordinate", coordinate))?
+            }
+            Coordinate::Split(x, y) => {
+                let (x, y) = (field(x, "x_coordinate")?, field(y, "y_coordinate")?);
+                Shape::Pixel {
+                    x: x.parse().map_err(|_| invalid("x_coordinate", x))?,
+                    y: y.parse().map_err(|_| invalid("y_coordinate", y))?,
+                }
+            }
+        };
+
+        // Resolve positions before emitting anything so a bad row adds no records
+        let position = |x: i64, y: i64| -> Result<u64, Error> {
+            let out_of_bounds = || Error::InvalidField {
+                line,
+                field: "coordinate",
+                value: format!("{x},{y}"),
+            };
+            let x = x
+                .checked_sub(self.origin.0)
+                .and_then(|x| u32::try_from(x).ok())
+                .ok_or_else(out_of_bounds)?;
+            let y = y
+                .checked_sub(self.origin.1)
+                .and_then(|y| u32::try_from(y).ok())
+                .ok_or_else(out_of_bounds)?;
+            Position::new(x, y)
+                .to_index(self.size)
+                .map_err(|source| Error::OutOfBounds { line, source })
+        };
+        let positions = match shape {
+            Shape::Pixel { x, y } => vec![position(x, y)?],
+            Shape::Rect { x1, y1, x2, y2 } => vec![position(x1, y1)?, position(x2, y2)?],
+            Shape::Circle { x, y, r } => self.circle_positions(x, y, r),
+        };
+        if positions.is_empty() {
+            return Ok(());
+        }
+
+        let user = self.user_record(user, line)?;
+        let col = self
+            .color_index(color)
+            .ok_or_else(|| invalid("color", color_field))?;
+        if let Some(user) = user {
+            self.pending.push_back(user);
+        }
+        let record = match shape {
+            Shape::Pixel { .. } => CanvasRecord::PlacementInsert(PlacementInsert {
+                time,
+                pos: positions[0],
+                col,
+            }),
+            Shape::Rect { .. } => CanvasRecord::PlacementInsertFillQuiet(PlacementInsertFill {
+                time,
+                pos: (positions[0], positions[1]),
+                col,
+            }),
+            Shape::Circle { .. } => CanvasRecord::PlacementInsertBatchQuiet(
+                positions
+                    .into_iter()
+                    .map(|pos| PlacementInsert { time, pos, col })
+                    .collect(),
+            ),
+        };
+
+        self.pending.push_back(record);
+        Ok(())
+    }
+
+    // Palette index of `color`, defining it if it is new, or `None` if the palette is full
+    fn color_index(&mut self, color: [u8; 4]) -> Option<u32> {
+        if let Some(&col) = self.colors.get(&color) {
+            return Some(col);
+        }
+        if self.colors.len() as u64 >= MAX_COLORS {
+            return None;
+        }
+
+        let next = self.colors.len() as u32;
+        self.colors.insert(color, next);
+        self.pending
+            .push_back(CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: next,
+                colors: vec![color],
+            }));
+        Some(next)
+    }
+
+    // Index of every pixel of the circle within the canvas, in row-major order
+    fn circle_positions(&self, x: i64, y: i64, r: i64) -> Vec<u64> {
+        // Widened so that neither the bounds nor the squared distances overflow
+        let (x, y, r) = (x as i128, y as i128, r as i128);
+        let (left, top) = (self.origin.0 as i128, self.origin.1 as i128);
+        let (right, bottom) = (
+            left + self.size.0 as i128 - 1,
+            top + self.size.1 as i128 - 1,
+        );
+
+        let mut positions = Vec::new();
+        for py in (y - r).max(top)..=(y + r).min(bottom) {
+            for px in (x - r).max(left)..=(x + r).min(right) {
+                if (px - x).pow(2) + (py - y).pow(2) <= r * r {
+                    let pos = (py - top) * self.size.0 as i128 + (px - left);
+                    positions.push(pos as u64);
+                }
+            }
+        }
+
+        positions
+    }
+
+    // Identifier record for `user` if it differs from the previous row's
+    fn user_record(&mut self, user: &str, line: u64) -> Result<Option<CanvasRecord>, Error> {
+        if self.user.as_deref() == Some(user) {
+            return Ok(None);
+        }
+
+        let hash = parse_base64(user);
+        let record = match (*self.secret_users.get_or_insert(hash.is_some()), hash) {
+            (true, Some(hash)) => CanvasRecord::IdentifierSecret(hash),
+            (true, None) => {
+                return Err(Error::InvalidField {
+                    line,
+                    field: "user",
+                    value: user.to_string(),
+                });
+            }
+            (false, _) => CanvasRecord::IdentifierString(user.to_string()),
+        };
+        self.user = Some(user.to_string());
+        Ok(Some(record))
+    }
+}
+
+impl<R: BufRead> Iterator for Importer<R> {
+    type Item = Result<CanvasRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+
+            let result = match self.columns {
+                None => Columns::parse(&line).map(|columns| self.columns = Some(columns)),
+                Some(_) if line.trim().is_empty() => Ok(()),
+                Some(columns) => self.push_row(&line, columns),
+            };
+            if let Err(e) = result {
+                return Some(Err(e));
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// Parse `#RRGGBB` or an index into [`PALETTE_2017`].
+fn parse_color(s: &str) -> Option<[u8; 4]> {
+    if let Some(hex) = s.strip_prefix('#') {
+        let [r, g, b] = parse_hex(hex)?[..] else {
+            return None;
+        };
+        return Some([r, g, b, 0xFF]);
+    }
+
+    PALETTE_2017.get(s.parse::<usize>().ok()?).copied()
+}
+
+/// Split a CSV row, unquoting fields.
+fn split_csv(row: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.trim_end_matches(['\r', '\n']).chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn meta(size: (u32, u32)) -> CanvasMeta {
+        CanvasMeta {
+            name: "r/place".to_string(),
+            platform: "reddit.com".to_string(),
+            time: 0,
+            size,
+        }
+    }
+
+    fn import(csv: &str, size: (u32, u32), origin: (i64, i64)) -> Vec<CanvasRecord> {
+        Importer::new(csv.as_bytes(), meta(size), origin)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn reddit_split_csv() {
+        assert_eq!(split_csv(r#"a,"1,2",b"#), vec!["a", "1,2", "b"]);
+        assert_eq!(split_csv(r#""say ""hi""",,"#), vec![r#"say "hi""#, "", ""]);
+    }
+
+    #[test]
+    fn reddit_shape() {
+        assert_eq!(
+            Shape::parse("826,1048"),
+            Some(Shape::Pixel { x: 826, y: 1048 })
+        );
+        assert_eq!(
+            Shape::parse("-10,0,5,6"),
+            Some(Shape::Rect {
+                x1: -10,
+                y1: 0,
+                x2: 5,
+                y2: 6
+            })
+        );
+        assert_eq!(
+            Shape::parse("{X: 424, Y: -336, R: 3}"),
+            Some(Shape::Circle {
+                x: 424,
+                y: -336,
+                r: 3
+            })
+        );
+        assert_eq!(Shape::parse("1,2,3"), None);
+    }
+
+    #[test]
+    fn reddit_import_2017() {
+        let csv = "\
+ts,user_hash,x_coordinate,y_coordinate,color
+2017-03-31 00:44:55.421 UTC,aGVsbG8=,1,0,5
+2017-03-31 00:44:56.000 UTC,aGVsbG8=,0,1,5
+";
+        let t0 = 1_490_921_095_421;
+
+        assert_eq!(
+            import(csv, (2, 2), (0, 0))[1..],
+            [
+                CanvasRecord::PaletteInsert(PaletteInsert {
+                    offset: 0,
+                    colors: vec![PALETTE_2017[5]]
+                }),
+                CanvasRecord::IdentifierSecret(b"hello".to_vec()),
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0,
+                    pos: 1,
+                    col: 0
+                }),
+                CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: t0 + 579,
+                    pos: 2,
+                    col: 0
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn reddit_import_2023() {
+        let csv = "\
+timestamp,user,coordinate,pixel_color
+2023-07-20 13:00:26.088 UTC,user-1,\"-2,-2\",#FF4500
+2023-07-20 13:00:27 UTC,bW9k,\"-2,-2,-1,-1\",#FFFFFF
+2023-07-20 13:00:28 UTC,bW9k,\"{X: 0, Y: 0, R: 1}\",#FF4500
+";
+        let t0 = 1_689_858_026_088;
+        let records = import(csv, (4, 4), (-2, -2));
+        let place = |time, pos| PlacementInsert { time, pos, col: 0 };
+
+        assert_eq!(
+            records[1..],
+            [
+                CanvasRecord::PaletteInsert(PaletteInsert {
+                    offset: 0,
+                    colors: vec![[0xFF, 0x45, 0x00, 0xFF]]
+                }),
+                CanvasRecord::IdentifierString("user-1".to_string()),
+                CanvasRecord::PlacementInsert(place(t0, 0)),
+                CanvasRecord::PaletteInsert(PaletteInsert {
+                    offset: 1,
+                    colors: vec![[0xFF; 4]]
+                }),
+                CanvasRecord::IdentifierString("bW9k".to_string()),
+                CanvasRecord::PlacementInsertFillQuiet(PlacementInsertFill {
+                    time: t0 + 912,
+                    pos: (0, 5),
+                    col: 1
+                }),
+                CanvasRecord::PlacementInsertBatchQuiet(vec![
+                    place(t0 + 1912, 6),
+                    place(t0 + 1912, 9),
+                    place(t0 + 1912, 10),
+                    place(t0 + 1912, 11),
+                    place(t0 + 1912, 14),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reddit_import_circle() {
+        let csv = "\
+timestamp,user,coordinate,pixel_color
+2023-07-20 13:00:26 UTC,bW9k,\"{X: 0, Y: 0, R: 1}\",#FF4500
+2023-07-20 13:00:27 UTC,bW9k,\"{X: 100, Y: 100, R: 2}\",#FF4500
+2023-07-20 13:00:28 UTC,bW9k,\"{X: 0, Y: 0, R: 9223372036854775807}\",#FF4500
+";
+        let t0 = 1_689_858_026_000;
+        let records = import(csv, (2, 2), (0, 0));
+        let place = |time, pos| PlacementInsert { time, pos, col: 0 };
+
+        assert_eq!(
+            records[2..],
+            [
+                CanvasRecord::IdentifierSecret(b"mod".to_vec()),
+                // Clipped to the canvas
+                CanvasRecord::PlacementInsertBatchQuiet(vec![
+                    place(t0, 0),
+                    place(t0, 1),
+                    place(t0, 2),
+                ]),
+                // The circle at (100, 100) is entirely outside the canvas and skipped, while the
+                // largest radius covers all of it
+                CanvasRecord::PlacementInsertBatchQuiet(vec![
+                    place(t0 + 2000, 0),
+                    place(t0 + 2000, 1),
+                    place(t0 + 2000, 2),
+                    place(t0 + 2000, 3),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reddit_import_errors() {
+        let error = |csv: &str| {
+            Importer::new(csv.as_bytes(), meta((4, 4)), (0, 0))
+                .find_map(Result::err)
+                .expect("imported invalid csv")
+        };
+
+        assert!(matches!(
+            error("timestamp,user_id,pixel_color\n"),
+            Error::MissingField { line: 1, .. }
+        ));
+        assert!(matches!(
+            error(
+                "timestamp,user_id,pixel_color,coordinate\n2022-04-04 00:53:51.577 UTC,a,#FFFFFF,\"4,0\""
+            ),
+            Error::OutOfBounds { line: 2, .. }
+        ));
+        assert!(matches!(
+            error(
+                "timestamp,user_id,pixel_color,coordinate\n2022-04-04 00:53:51.577 UTC,a,#FFF,\"0,0\""
+            ),
+            Error::InvalidField {
+                line: 2,
+                field: "color",
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(
+                "timestamp,user_id,pixel_color,coordinate\n2022-04-04 00:53:51.577 UTC,aGk=,#FFFFFF,\"0,0\"\n2022-04-04 00:53:52.577 UTC,user-1,#FFFFFF,\"0,0\""
+            ),
+            Error::InvalidField {
+                line: 3,
+                field: "user",
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(
+                "timestamp,user_id,pixel_color,coordinate\n2022-04-04 00:53:51.577 UTC,a,#FFFFFF,\"-9223372036854775808,0\""
+            ),
+            Error::InvalidField {
+                line: 2,
+                field: "coordinate",
+                ..
+            }
+        ));
+
+        // More colors than the palette holds
+        let csv = "timestamp,user_id,pixel_color,coordinate\n2022-04-04 00:53:51.577 UTC,a,#FFFFFF,\"0,0\"";
+        let mut importer = Importer::new(csv.as_bytes(), meta((4, 4)), (0, 0));
+        importer.colors = (0..MAX_COLORS as u32)
+            .map(|col| (col.to_le_bytes(), col))
+            .collect();
+        assert!(matches!(
+            importer.find_map(Result::err),
+            Some(Error::InvalidField {
+                line: 2,
+                field: "color",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+use std::num::NonZeroU64;
+
+use crate::{
+    CanvasRecord,
+    state::{self, CanvasState},
+};
+
+/// Iterator inserting a [`CanvasKeyframe`](crate::CanvasKeyframe) into a stream every
+/// `interval` of time, for [`render::seek`](crate::render::seek) to start from.
+///
+/// A keyframe follows the first record at or past each interval since the previous keyframe
+/// (or the first timed record) and holds the canvas including that record. Records that fail to
+/// apply are yielded as errors and leave the canvas untouched.
+#[derive(Debug)]
+pub struct Keyframes<I> {
+    records: I,
+    interval: NonZeroU64,
+    state: CanvasState,
+    next_time: Option<u64>,
+    pending: Option<CanvasRecord>,
+}
+
+impl<I: Iterator<Item = CanvasRecord>> Keyframes<I> {
+    pub fn new(records: impl IntoIterator<IntoIter = I>, interval: NonZeroU64) -> Self {
+        Self {
+            records: records.into_iter(),
+            interval,
+            state: CanvasState::default(),
+            next_time: None,
+            pending: None,
+        }
+    }
+
+    pub fn state(&self) -> &CanvasState {
+        &self.state
+    }
+}
+
+impl<I: Iterator<Item = CanvasRecord>> Iterator for Keyframes<I> {
+    type Item = Result<CanvasRecord, state::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(keyframe) = self.pending.take() {
+            return Some(Ok(keyframe));
+        }
+
+        let record = self.records.next()?;
+        if let Err(e) = self.state.apply(&record) {
+            return Some(Err(e));
+        }
+
+        if record.time().is_some() && !matches!(record, CanvasRecord::CanvasKeyframe(_)) {
+            // Batches leave the state at the time of their last placement
+            let time = self.state.time();
+            let next = *self
+                .next_time
+                .get_or_insert(time.saturating_add(self.interval.get()));
+            if time >= next {
+                self.pending = Some(self.state.keyframe().into());
+                self.next_time = Some(time.saturating_add(self.interval.get()));
+            }
+        }
+
+        Some(Ok(record))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        CanvasMeta, Identifier, PaletteInsert, PlacementInsert,
+        render::{self, RenderOptions},
+    };
+
+    use super::*;
+
+    fn records() -> Vec<CanvasRecord> {
+        let place = |time, pos, col| PlacementInsert { time, pos, col };
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (2, 2),
+            }),
+            CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: 0,
+                colors: vec![[0xFF; 4], [0x00, 0x00, 0x00, 0xFF]],
+            }),
+            CanvasRecord::PlacementInsert(place(4, 0, 0)),
+            CanvasRecord::PlacementInsertBatch(vec![place(9, 1, 1), place(12, 2, 0)]),
+            CanvasRecord::IdentifierNumeric(1),
+            CanvasRecord::PlacementInsert(place(15, 0, 1)),
+            CanvasRecord::PlacementInsert(place(31, 3, 1)),
+        ]
+    }
+
+    #[test]
+    fn keyframe_insert() {
+        let interval = NonZeroU64::new(10).unwrap();
+        let records: Vec<CanvasRecord> = Keyframes::new(records(), interval)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let keyframes: Vec<(usize, u64)> = records
+            .iter()
+            .enumerate()
+            .filter_map(|(i, record)| match record {
+                CanvasRecord::CanvasKeyframe(rec) => Some((i, rec.time)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keyframes, vec![(4, 12), (8, 31)]);
+
+        let CanvasRecord::CanvasKeyframe(keyframe) = &records[4] else {
+            unreachable!()
+        };
+        assert_eq!(keyframe.pixels, vec![Some(0), Some(1), Some(0), None]);
+    }
+
+    #[test]
+    fn keyframe_seek() {
+        let interval = NonZeroU64::new(10).unwrap();
+        let records: Vec<CanvasRecord> = Keyframes::new(records(), interval)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        for time in [0, 9, 12, 15, 30, 31, u64::MAX] {
+            let renderer = render::seek(&records, time).unwrap();
+            let options = RenderOptions::default();
+            assert_eq!(
+                renderer.image(&options).unwrap(),
+                render::render_at(&records, time, &options).unwrap(),
+                "seek to {time}"
+            );
+        }
+
+        let renderer = render::seek(&records, 20).unwrap();
+        assert_eq!(renderer.state().author(), Some(&Identifier::Numerical(1)));
+        assert_eq!(renderer.palette().len(), 2);
+    }
+}
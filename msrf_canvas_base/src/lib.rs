@@ -1,10 +1,27 @@
 use std::num::NonZeroU32;
 
+use position::{Position, Rect};
+
+pub mod analytics;
+pub mod anonymise;
 pub mod codec;
+pub mod heatmap;
+pub mod history;
+pub mod identifier;
+pub mod import;
+pub mod keyframe;
+pub mod palette;
+pub mod position;
+pub mod render;
+pub mod state;
+pub mod timelapse;
+pub mod validate;
 
-pub const CURRENT_VERSION: u16 = 0;
+pub const CURRENT_VERSION: u16 = 1;
 
 pub const CANVAS_META_TYPE_ID: u16 = 0x0000;
+pub const CANVAS_RESIZE_TYPE_ID: u16 = 0x0001;
+pub const CANVAS_KEYFRAME_TYPE_ID: u16 = 0x0002;
 pub const PALETTE_INSERT_TYPE_ID: u16 = 0x0010;
 pub const PALETTE_REMOVE_TYPE_ID: u16 = 0x0011;
 pub const PLACEMENT_INSERT_TYPE_ID: u16 = 0x0020;
@@ -15,6 +32,10 @@ pub const PLACEMENT_REMOVE_TYPE_ID: u16 = 0x0024;
 pub const PLACEMENT_REMOVE_SILENT_TYPE_ID: u16 = 0x0025;
 pub const PLACEMENT_REMOVE_FILL_TYPE_ID: u16 = 0x0026;
 pub const PLACEMENT_REMOVE_FILL_SILENT_TYPE_ID: u16 = 0x0027;
+pub const PLACEMENT_INSERT_BATCH_TYPE_ID: u16 = 0x0028;
+pub const PLACEMENT_INSERT_BATCH_SILENT_TYPE_ID: u16 = 0x0029;
+pub const PLACEMENT_REMOVE_BATCH_TYPE_ID: u16 = 0x002A;
+pub const PLACEMENT_REMOVE_BATCH_SILENT_TYPE_ID: u16 = 0x002B;
 pub const IDENTIFIER_NUMERIC_TYPE_ID: u16 = 0x0030;
 pub const IDENTIFIER_STRING_TYPE_ID: u16 = 0x0031;
 pub const IDENTIFIER_SECRET_TYPE_ID: u16 = 0x0032;
@@ -33,6 +54,8 @@ macro_rules! event_from {
 #[repr(u16)]
 pub enum CanvasRecord {
     CanvasMeta(CanvasMeta) = CANVAS_META_TYPE_ID,
+    CanvasResize(CanvasResize) = CANVAS_RESIZE_TYPE_ID,
+    CanvasKeyframe(CanvasKeyframe) = CANVAS_KEYFRAME_TYPE_ID,
     PaletteInsert(PaletteInsert) = PALETTE_INSERT_TYPE_ID,
     PaletteRemove(PaletteRemove) = PALETTE_REMOVE_TYPE_ID,
     PlacementInsert(PlacementInsert) = PLACEMENT_INSERT_TYPE_ID,
@@ -43,12 +66,18 @@ pub enum CanvasRecord {
     PlacementRemoveQuiet(PlacementRemove) = PLACEMENT_REMOVE_SILENT_TYPE_ID,
     PlacementRemoveFill(PlacementRemoveFill) = PLACEMENT_REMOVE_FILL_TYPE_ID,
     PlacementRemoveFillQuiet(PlacementRemoveFill) = PLACEMENT_REMOVE_FILL_SILENT_TYPE_ID,
+    PlacementInsertBatch(Vec<PlacementInsert>) = PLACEMENT_INSERT_BATCH_TYPE_ID,
+    PlacementInsertBatchQuiet(Vec<PlacementInsert>) = PLACEMENT_INSERT_BATCH_SILENT_TYPE_ID,
+    PlacementRemoveBatch(Vec<PlacementRemove>) = PLACEMENT_REMOVE_BATCH_TYPE_ID,
+    PlacementRemoveBatchQuiet(Vec<PlacementRemove>) = PLACEMENT_REMOVE_BATCH_SILENT_TYPE_ID,
     IdentifierNumeric(u64) = IDENTIFIER_NUMERIC_TYPE_ID,
     IdentifierString(String) = IDENTIFIER_STRING_TYPE_ID,
     IdentifierSecret(Vec<u8>) = IDENTIFIER_SECRET_TYPE_ID,
 }
 
 event_from!(CanvasMeta);
+event_from!(CanvasResize);
+event_from!(CanvasKeyframe);
 event_from!(PaletteInsert);
 event_from!(PaletteRemove);
 event_from!(PlacementInsert);
@@ -57,19 +86,157 @@ event_from!(PlacementRemove);
 event_from!(PlacementRemoveFill);
 
 impl CanvasRecord {
-    fn raw_id(&self) -> u16 {
+    pub fn raw_id(&self) -> u16 {
         // SAFETY: Because `Self` is marked `repr(u16)` we can read the discriminant safely.
         unsafe { *<*const _>::from(self).cast::<u16>() }
     }
 
+    /// Time of the record, for batches the time of the first placement.
+    pub fn time(&self) -> Option<u64> {
+        match self {
+            Self::CanvasMeta(rec) => Some(rec.time),
+            Self::CanvasResize(rec) => Some(rec.time),
+            Self::CanvasKeyframe(rec) => Some(rec.time),
+            Self::PlacementInsert(rec) | Self::PlacementInsertQuiet(rec) => Some(rec.time),
+            Self::PlacementInsertFill(rec) | Self::PlacementInsertFillQuiet(rec) => Some(rec.time),
+            Self::PlacementRemove(rec) | Self::PlacementRemoveQuiet(rec) => Some(rec.time),
+            Self::PlacementRemoveFill(rec) | Self::PlacementRemoveFillQuiet(rec) => Some(rec.time),
+            Self::PlacementInsertBatch(batch) | Self::PlacementInsertBatchQuiet(batch) => {
+                batch.first().map(|rec| rec.time)
+            }
+            Self::PlacementRemoveBatch(batch) | Self::PlacementRemoveBatchQuiet(batch) => {
+                batch.first().map(|rec| rec.time)
+            }
+            Self::PaletteInsert(_)
+            | Self::PaletteRemove(_)
+            | Self::IdentifierNumeric(_)
+            | Self::IdentifierString(_)
+            | Self::IdentifierSecret(_) => None,
+        }
+    }
+
+    /// Time of every placement of the record in order, one per entry for batches.
+    pub fn times(&self) -> impl Iterator<Item = u64> + '_ {
+        let (inserts, removes, single): (&[PlacementInsert], &[PlacementRemove], _) = match self {
+            Self::PlacementInsertBatch(batch) | Self::PlacementInsertBatchQuiet(batch) => {
+                (batch, &[], None)
+            }
+            Self::PlacementRemoveBatch(batch) | Self::PlacementRemoveBatchQuiet(batch) => {
+                (&[], batch, None)
+            }
+            _ => (&[], &[], self.time()),
+        };
+
+        single
+            .into_iter()
+            .chain(inserts.iter().map(|rec| rec.time))
+            .chain(removes.iter().map(|rec| rec.time))
+    }
+
+    /// Identifier carried by an identifier record.
+    pub fn identifier(&self) -> Option<Identifier> {
+        match self {
+            Self::IdentifierNumeric(n) => Some(Identifier::Numerical(*n)),
+            Self::IdentifierString(s) => Some(Identifier::String(s.clone())),
+            Self::IdentifierSecret(raw) => Some(Identifier::Secret(raw.clone())),
+            _ => None,
+        }
+    }
+
     pub fn is_silent(&self) -> bool {
+        matches!(
+            self,
+            Self::PlacementInsertQuiet(_)
+                | Self::PlacementInsertFillQuiet(_)
+                | Self::PlacementRemoveQuiet(_)
+                | Self::PlacementRemoveFillQuiet(_)
+                | Self::PlacementInsertBatchQuiet(_)
+                | Self::PlacementRemoveBatchQuiet(_)
+        )
+    }
+}
+
+/// Borrowed form of [`CanvasRecord`] referencing the value it was decoded from.
+///
+/// Strings, secrets and palette colors borrow from the value, fixed size records are copied and
+/// batches and keyframes are decoded into an owned list.
+#[derive(Debug, Clone, PartialEq)]
+#[repr(u16)]
+pub enum CanvasRecordRef<'a> {
+    CanvasMeta(CanvasMetaRef<'a>) = CANVAS_META_TYPE_ID,
+    CanvasResize(CanvasResize) = CANVAS_RESIZE_TYPE_ID,
+    CanvasKeyframe(CanvasKeyframe) = CANVAS_KEYFRAME_TYPE_ID,
+    PaletteInsert(PaletteInsertRef<'a>) = PALETTE_INSERT_TYPE_ID,
+    PaletteRemove(PaletteRemove) = PALETTE_REMOVE_TYPE_ID,
+    PlacementInsert(PlacementInsert) = PLACEMENT_INSERT_TYPE_ID,
+    PlacementInsertQuiet(PlacementInsert) = PLACEMENT_INSERT_SILENT_TYPE_ID,
+    PlacementInsertFill(PlacementInsertFill) = PLACEMENT_INSERT_FILL_TYPE_ID,
+    PlacementInsertFillQuiet(PlacementInsertFill) = PLACEMENT_INSERT_FILL_SILENT_TYPE_ID,
+    PlacementRemove(PlacementRemove) = PLACEMENT_REMOVE_TYPE_ID,
+    PlacementRemoveQuiet(PlacementRemove) = PLACEMENT_REMOVE_SILENT_TYPE_ID,
+    PlacementRemoveFill(PlacementRemoveFill) = PLACEMENT_REMOVE_FILL_TYPE_ID,
+    PlacementRemoveFillQuiet(PlacementRemoveFill) = PLACEMENT_REMOVE_FILL_SILENT_TYPE_ID,
+    PlacementInsertBatch(Vec<PlacementInsert>) = PLACEMENT_INSERT_BATCH_TYPE_ID,
+    PlacementInsertBatchQuiet(Vec<PlacementInsert>) = PLACEMENT_INSERT_BATCH_SILENT_TYPE_ID,
+    PlacementRemoveBatch(Vec<PlacementRemove>) = PLACEMENT_REMOVE_BATCH_TYPE_ID,
+    PlacementRemoveBatchQuiet(Vec<PlacementRemove>) = PLACEMENT_REMOVE_BATCH_SILENT_TYPE_ID,
+    IdentifierNumeric(u64) = IDENTIFIER_NUMERIC_TYPE_ID,
+    IdentifierString(&'a str) = IDENTIFIER_STRING_TYPE_ID,
+    IdentifierSecret(&'a [u8]) = IDENTIFIER_SECRET_TYPE_ID,
+}
+
+impl CanvasRecordRef<'_> {
+    pub fn raw_id(&self) -> u16 {
+        // SAFETY: Because `Self` is marked `repr(u16)` we can read the discriminant safely.
+        unsafe { *<*const _>::from(self).cast::<u16>() }
+    }
+
+    pub fn to_owned(&self) -> CanvasRecord {
+        self.clone().into_owned()
+    }
+
+    pub fn into_owned(self) -> CanvasRecord {
         match self {
-            Self::PlacementInsertQuiet(_) | Self::PlacementInsertFillQuiet(_) | Self::PlacementRemoveQuiet(_) | Self::PlacementRemoveFillQuiet(_) => true,
-            _ => false,
+            Self::CanvasMeta(rec) => CanvasRecord::CanvasMeta(rec.to_owned()),
+            Self::CanvasResize(rec) => CanvasRecord::CanvasResize(rec),
+            Self::CanvasKeyframe(rec) => CanvasRecord::CanvasKeyframe(rec),
+            Self::PaletteInsert(rec) => CanvasRecord::PaletteInsert(rec.to_owned()),
+            Self::PaletteRemove(rec) => CanvasRecord::PaletteRemove(rec),
+            Self::PlacementInsert(rec) => CanvasRecord::PlacementInsert(rec),
+            Self::PlacementInsertQuiet(rec) => CanvasRecord::PlacementInsertQuiet(rec),
+            Self::PlacementInsertFill(rec) => CanvasRecord::PlacementInsertFill(rec),
+            Self::PlacementInsertFillQuiet(rec) => CanvasRecord::PlacementInsertFillQuiet(rec),
+            Self::PlacementRemove(rec) => CanvasRecord::PlacementRemove(rec),
+            Self::PlacementRemoveQuiet(rec) => CanvasRecord::PlacementRemoveQuiet(rec),
+            Self::PlacementRemoveFill(rec) => CanvasRecord::PlacementRemoveFill(rec),
+            Self::PlacementRemoveFillQuiet(rec) => CanvasRecord::PlacementRemoveFillQuiet(rec),
+            Self::PlacementInsertBatch(rec) => CanvasRecord::PlacementInsertBatch(rec),
+            Self::PlacementInsertBatchQuiet(rec) => CanvasRecord::PlacementInsertBatchQuiet(rec),
+            Self::PlacementRemoveBatch(rec) => CanvasRecord::PlacementRemoveBatch(rec),
+            Self::PlacementRemoveBatchQuiet(rec) => CanvasRecord::PlacementRemoveBatchQuiet(rec),
+            Self::IdentifierNumeric(n) => CanvasRecord::IdentifierNumeric(n),
+            Self::IdentifierString(s) => CanvasRecord::IdentifierString(s.to_string()),
+            Self::IdentifierSecret(raw) => CanvasRecord::IdentifierSecret(raw.to_vec()),
+        }
+    }
+}
+
+impl From<Identifier> for CanvasRecord {
+    fn from(value: Identifier) -> Self {
+        match value {
+            Identifier::Numerical(n) => CanvasRecord::IdentifierNumeric(n),
+            Identifier::String(s) => CanvasRecord::IdentifierString(s),
+            Identifier::Secret(raw) => CanvasRecord::IdentifierSecret(raw),
         }
     }
 }
 
+impl From<CanvasRecordRef<'_>> for CanvasRecord {
+    fn from(value: CanvasRecordRef<'_>) -> Self {
+        value.into_owned()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct CanvasMeta {
     pub name: String,
@@ -78,12 +245,110 @@ pub struct CanvasMeta {
     pub size: (u32, u32),
 }
 
+/// Conversions between placement `pos` values and coordinates on a canvas of the meta's size.
+///
+/// These only hold until the first [`CanvasResize`], after which positions must be converted
+/// with the size of the latest resize instead.
+impl CanvasMeta {
+    /// Coordinates of the linear `pos` used by placement records.
+    pub fn position(&self, pos: u64) -> Result<Position, position::Error> {
+        Position::from_index(pos, self.size)
+    }
+
+    /// Linear `pos` used by placement records for `position`.
+    pub fn index(&self, position: Position) -> Result<u64, position::Error> {
+        position.to_index(self.size)
+    }
+
+    /// Rectangle covered by the corners of a fill record.
+    pub fn fill_rect(&self, corners: (u64, u64)) -> Result<Rect, position::Error> {
+        Rect::from_fill(corners, self.size)
+    }
+}
+
+/// Change of the canvas size partway through a stream, such as an expansion during an event.
+///
+/// Positions in records after the resize are relative to the new `size`. Existing pixels move
+/// so that the previous canvas starts at `offset` within the new one, and pixels that end up
+/// outside the new canvas are discarded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanvasResize {
+    pub time: u64,
+    pub size: (u32, u32),
+    pub offset: (u32, u32),
+}
+
+impl CanvasResize {
+    /// Where a pixel of the previous canvas ends up, or `None` if it is discarded.
+    pub fn remap(&self, pos: Position) -> Option<Position> {
+        let pos = Position::new(
+            pos.x.checked_add(self.offset.0)?,
+            pos.y.checked_add(self.offset.1)?,
+        );
+        pos.is_within(self.size).then_some(pos)
+    }
+
+    /// [`remap`](Self::remap) for the linear `pos` of a canvas of size `previous`.
+    pub fn remap_index(&self, pos: u64, previous: (u32, u32)) -> Option<u64> {
+        let pos = Position::from_index(pos, previous).ok()?;
+        self.remap(pos)?.to_index(self.size).ok()
+    }
+}
+
+/// Full canvas contents at a point in time, so replay can start from here rather than from the
+/// beginning of the stream.
+///
+/// `pixels` holds the palette index of every pixel of a canvas of `size` in row-major order, or
+/// `None` if it is empty, as in [`CanvasState`](state::CanvasState). Codecs store it compressed.
+/// A keyframe restates the canvas produced by the records before it, so replaying it in order
+/// changes nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanvasKeyframe {
+    pub time: u64,
+    pub size: (u32, u32),
+    pub pixels: Vec<Option<u32>>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct PaletteInsert {
     pub offset: u32,
     pub colors: Vec<[u8; 4]>,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CanvasMetaRef<'a> {
+    pub name: &'a str,
+    pub platform: &'a str,
+    pub time: u64,
+    pub size: (u32, u32),
+}
+
+impl CanvasMetaRef<'_> {
+    pub fn to_owned(&self) -> CanvasMeta {
+        CanvasMeta {
+            name: self.name.to_string(),
+            platform: self.platform.to_string(),
+            time: self.time,
+            size: self.size,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PaletteInsertRef<'a> {
+    pub offset: u32,
+    pub colors: &'a [[u8; 4]],
+}
+
+impl PaletteInsertRef<'_> {
+    pub fn to_owned(&self) -> PaletteInsert {
+        PaletteInsert {
+            offset: self.offset,
+            colors: self.colors.to_vec(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct PaletteRemove {
     pub offset: u32,
@@ -116,18 +381,40 @@ pub struct PlacementRemoveFill {
     pub pos: (u64, u64),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Author of placements.
+///
+/// An identifier record sets the author of every placement that follows it, until the next
+/// identifier record or [`CanvasMeta`]. Placements before any identifier have no author, so a
+/// stream only needs to repeat an identifier when the author changes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Identifier {
     Numerical(u64),
     String(String),
     Secret(Vec<u8>),
 }
 
+/// Handle to an identifier in an [`IdentifierTable`](identifier::IdentifierTable).
+///
+/// The low 31 bits hold the index and the high bit marks a unique handle, an anonymous author
+/// distinct from every other handle that has no table entry. An index of `0x7FFFFFFF` is
+/// reserved for no identifier.
 //TODO: Size optimisation (NonMaximum???)
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct MetaIdIndex(u32);
 
 impl MetaIdIndex {
+    pub const NONE: Self = Self(0x7FFFFFFF);
+    pub const MAX_INDEX: usize = 0x7FFFFFFE;
+
+    /// Handle to `index`, or `None` if it is above [`MAX_INDEX`](Self::MAX_INDEX).
+    pub fn new(index: usize, unique: bool) -> Option<Self> {
+        if index > Self::MAX_INDEX {
+            return None;
+        }
+
+        Some(Self(index as u32 | (unique as u32) << 31))
+    }
+
     pub fn is_unique(&self) -> bool {
         (self.0 >> 31) & 1 > 0
     }
@@ -156,5 +443,31 @@ mod test {
         let id = MetaIdIndex(0xFFFFFFFF);
         assert!(id.is_unique());
         assert!(id.is_none());
+
+        assert_eq!(MetaIdIndex::new(0x12, true), Some(MetaIdIndex(0x80000012)));
+        assert_eq!(
+            MetaIdIndex::new(0x7FFFFFFE, false),
+            Some(MetaIdIndex(0x7FFFFFFE))
+        );
+        assert_eq!(MetaIdIndex::new(0x7FFFFFFF, false), None);
+        assert!(MetaIdIndex::NONE.is_none());
+    }
+
+    #[test]
+    fn record_times() {
+        let place = |time| PlacementRemove { time, pos: 0 };
+        let batch = CanvasRecord::PlacementRemoveBatch(vec![place(3), place(5)]);
+        assert_eq!(batch.times().collect::<Vec<_>>(), vec![3, 5]);
+        assert_eq!(batch.time(), Some(3));
+
+        let single = CanvasRecord::PlacementRemove(place(7));
+        assert_eq!(single.times().collect::<Vec<_>>(), vec![7]);
+        assert_eq!(CanvasRecord::IdentifierNumeric(1).times().next(), None);
+        assert_eq!(
+            CanvasRecord::PlacementInsertBatch(Vec::new())
+                .times()
+                .next(),
+            None
+        );
     }
-}
\ No newline at end of file
+}
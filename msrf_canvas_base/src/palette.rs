@@ -0,0 +1,268 @@
+use std::fmt::Display;
+
+use crate::{CanvasRecord, PaletteInsert, PaletteRemove};
+
+/// Number of palette slots a [`Palette`] holds, far above any known event (pxls.space and
+/// r/place use at most 32 colours).
+pub const MAX_COLORS: u64 = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UndefinedColor(u32),
+    RemovedColor(u32),
+    /// An insert reaching past [`MAX_COLORS`].
+    TooLarge {
+        offset: u32,
+        len: usize,
+    },
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UndefinedColor(col) => write!(f, "color {col} was never defined"),
+            Error::RemovedColor(col) => write!(f, "color {col} was removed"),
+            Error::TooLarge { offset, len } => write!(
+                f,
+                "{len} colors at offset {offset} exceed the {MAX_COLORS} palette slots"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Slot {
+    Undefined,
+    Color([u8; 4]),
+    Removed,
+}
+
+/// Palette produced by replaying [`PaletteInsert`] and [`PaletteRemove`] records.
+///
+/// Slots are addressed by absolute index and never shift: inserting overwrites the slots
+/// starting at `offset` and removing leaves a gap that may later be filled by another insert.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Palette {
+    slots: Vec<Slot>,
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of slots up to and including the highest slot ever touched.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn slot(&self, col: u32) -> Slot {
+        self.slots
+            .get(col as usize)
+            .copied()
+            .unwrap_or(Slot::Undefined)
+    }
+
+    pub fn resolve(&self, col: u32) -> Option<[u8; 4]> {
+        match self.slot(col) {
+            Slot::Color(color) => Some(color),
+            Slot::Undefined | Slot::Removed => None,
+        }
+    }
+
+    /// Resolve `col`, reporting why it has no color.
+    pub fn check(&self, col: u32) -> Result<[u8; 4], Error> {
+        match self.slot(col) {
+            Slot::Color(color) => Ok(color),
+            Slot::Undefined => Err(Error::UndefinedColor(col)),
+            Slot::Removed => Err(Error::RemovedColor(col)),
+        }
+    }
+
+    /// Define the colors of `record`, or an error leaving the palette untouched if they reach
+    /// past [`MAX_COLORS`].
+    pub fn insert(&mut self, record: &PaletteInsert) -> Result<(), Error> {
+        let too_large = || Error::TooLarge {
+            offset: record.offset,
+            len: record.colors.len(),
+        };
+        let end = (record.offset as u64)
+            .checked_add(record.colors.len() as u64)
+            .filter(|end| *end <= MAX_COLORS)
+            .ok_or_else(too_large)?;
+
+        let (start, end) = (record.offset as usize, end as usize);
+        if self.slots.len() < end {
+            self.slots.resize(end, Slot::Undefined);
+        }
+
+        for (slot, color) in self.slots[start..end].iter_mut().zip(&record.colors) {
+            *slot = Slot::Color(*color);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, record: &PaletteRemove) {
+        // Slots past the end were never defined, so there is nothing to remove
+        let end = (record.offset as usize)
+            .saturating_add(record.length.get() as usize)
+            .min(self.slots.len());
+        let start = (record.offset as usize).min(end);
+
+        for slot in &mut self.slots[start..end] {
+            *slot = Slot::Removed;
+        }
+    }
+
+    /// Apply a record, checking that placements reference a defined color.
+    ///
+    /// Palette records update the palette, a [`CanvasMeta`](crate::CanvasMeta) starts a new
+    /// canvas with an empty palette as in [`CanvasState::apply`], placements are checked against
+    /// it and every other record is ignored.
+    ///
+    /// [`CanvasState::apply`]: crate::state::CanvasState::apply
+    pub fn apply(&mut self, record: &CanvasRecord) -> Result<(), Error> {
+        match record {
+            CanvasRecord::CanvasMeta(_) => *self = Self::new(),
+            CanvasRecord::PaletteInsert(rec) => self.insert(rec)?,
+            CanvasRecord::PaletteRemove(rec) => self.remove(rec),
+            CanvasRecord::PlacementInsert(rec) | CanvasRecord::PlacementInsertQuiet(rec) => {
+                self.check(rec.col)?;
+            }
+            CanvasRecord::PlacementInsertFill(rec)
+            | CanvasRecord::PlacementInsertFillQuiet(rec) => {
+                self.check(rec.col)?;
+            }
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => {
+                batch
+                    .iter()
+                    .try_for_each(|rec| self.check(rec.col).map(|_| ()))?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use crate::{CanvasMeta, PlacementInsert};
+
+    use super::*;
+
+    const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+    const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+    #[test]
+    fn palette_insert() {
+        let mut palette = Palette::new();
+        palette
+            .insert(&PaletteInsert {
+                offset: 2,
+                colors: vec![WHITE, BLACK],
+            })
+            .unwrap();
+
+        assert_eq!(palette.len(), 4);
+        assert_eq!(palette.resolve(2), Some(WHITE));
+        assert_eq!(palette.resolve(3), Some(BLACK));
+        assert_eq!(palette.slot(0), Slot::Undefined);
+        assert_eq!(palette.slot(4), Slot::Undefined);
+
+        // Overwrite
+        palette
+            .insert(&PaletteInsert {
+                offset: 3,
+                colors: vec![WHITE],
+            })
+            .unwrap();
+        assert_eq!(palette.resolve(3), Some(WHITE));
+
+        // Slots past the maximum are rejected without allocating
+        let huge = PaletteInsert {
+            offset: u32::MAX,
+            colors: vec![WHITE],
+        };
+        assert_eq!(
+            palette.insert(&huge),
+            Err(Error::TooLarge {
+                offset: u32::MAX,
+                len: 1
+            })
+        );
+        assert_eq!(palette.len(), 4);
+    }
+
+    #[test]
+    fn palette_remove() {
+        let mut palette = Palette::new();
+        palette
+            .insert(&PaletteInsert {
+                offset: 0,
+                colors: vec![WHITE, BLACK, WHITE, BLACK],
+            })
+            .unwrap();
+        palette.remove(&PaletteRemove {
+            offset: 1,
+            length: NonZeroU32::new(2).unwrap(),
+        });
+
+        assert_eq!(palette.slot(0), Slot::Color(WHITE));
+        assert_eq!(palette.slot(1), Slot::Removed);
+        assert_eq!(palette.slot(2), Slot::Removed);
+        assert_eq!(palette.slot(3), Slot::Color(BLACK));
+        assert_eq!(palette.check(1), Err(Error::RemovedColor(1)));
+
+        // Refill the gap
+        palette
+            .insert(&PaletteInsert {
+                offset: 1,
+                colors: vec![BLACK],
+            })
+            .unwrap();
+        assert_eq!(palette.resolve(1), Some(BLACK));
+    }
+
+    #[test]
+    fn palette_apply_placement() {
+        let mut palette = Palette::new();
+        palette
+            .apply(&CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: 0,
+                colors: vec![WHITE],
+            }))
+            .unwrap();
+
+        let placement = |col| {
+            CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 0,
+                pos: 0,
+                col,
+            })
+        };
+        assert_eq!(palette.apply(&placement(0)), Ok(()));
+        assert_eq!(palette.apply(&placement(1)), Err(Error::UndefinedColor(1)));
+
+        // A new canvas starts with no colors
+        let meta = CanvasRecord::CanvasMeta(CanvasMeta {
+            name: "test".to_string(),
+            platform: "pxls.space".to_string(),
+            time: 0,
+            size: (1, 1),
+        });
+        palette.apply(&meta).unwrap();
+        assert!(palette.is_empty());
+        assert_eq!(palette.apply(&placement(0)), Err(Error::UndefinedColor(0)));
+    }
+}
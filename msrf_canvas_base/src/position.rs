@@ -0,0 +1,251 @@
+use std::fmt::Display;
+
+/// Largest canvas, in pixels, that replay allocates a buffer for.
+///
+/// This is far above any known event (the largest r/place canvas is 3000x2000) while keeping a
+/// canvas buffer to a few GiB, so hostile metadata cannot exhaust memory.
+pub const MAX_PIXELS: u64 = 1 << 28;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    IndexOutOfBounds { pos: u64, len: u64 },
+    OutOfBounds { pos: Position, size: (u32, u32) },
+    SizeMismatch { len: u64, size: (u32, u32) },
+    TooLarge { size: (u32, u32) },
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::IndexOutOfBounds { pos, len } => {
+                write!(f, "position {pos} out of bounds (canvas has {len} pixels)")
+            }
+            Error::OutOfBounds { pos, size } => write!(
+                f,
+                "position {pos} out of bounds (canvas is {}x{})",
+                size.0, size.1
+            ),
+            Error::SizeMismatch { len, size } => {
+                write!(f, "{len} pixels do not fill a {}x{} canvas", size.0, size.1)
+            }
+            Error::TooLarge { size } => write!(
+                f,
+                "{}x{} canvas is larger than {MAX_PIXELS} pixels",
+                size.0, size.1
+            ),
+        }
+    }
+}
+
+/// Number of pixels of a canvas of `size`, or an error if it is above [`MAX_PIXELS`].
+pub fn pixel_count(size: (u32, u32)) -> Result<usize, Error> {
+    (size.0 as u64)
+        .checked_mul(size.1 as u64)
+        .filter(|len| *len <= MAX_PIXELS)
+        .and_then(|len| usize::try_from(len).ok())
+        .ok_or(Error::TooLarge { size })
+}
+
+/// Pixel coordinates, with the origin in the top left corner of the canvas.
+///
+/// Records store positions as linear indices (`y * width + x`), so converting between the two
+/// requires the canvas size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Position {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Position {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn from_index(pos: u64, size: (u32, u32)) -> Result<Self, Error> {
+        let len = size.0 as u64 * size.1 as u64;
+        if pos >= len {
+            return Err(Error::IndexOutOfBounds { pos, len });
+        }
+
+        let width = size.0 as u64;
+        Ok(Self {
+            x: (pos % width) as u32,
+            y: (pos / width) as u32,
+        })
+    }
+
+    pub fn to_index(self, size: (u32, u32)) -> Result<u64, Error> {
+        if !self.is_within(size) {
+            return Err(Error::OutOfBounds { pos: self, size });
+        }
+
+        Ok(self.y as u64 * size.0 as u64 + self.x as u64)
+    }
+
+    pub fn is_within(&self, size: (u32, u32)) -> bool {
+        self.x < size.0 && self.y < size.1
+    }
+}
+
+impl From<(u32, u32)> for Position {
+    fn from(value: (u32, u32)) -> Self {
+        Self::new(value.0, value.1)
+    }
+}
+
+impl From<Position> for (u32, u32) {
+    fn from(value: Position) -> Self {
+        (value.x, value.y)
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {})", self.x, self.y)
+    }
+}
+
+/// Rectangle of pixels including both `min` and `max`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub min: Position,
+    pub max: Position,
+}
+
+impl Rect {
+    /// Rectangle spanning two opposite corners given in any order.
+    pub fn new(a: Position, b: Position) -> Self {
+        Self {
+            min: Position::new(a.x.min(b.x), a.y.min(b.y)),
+            max: Position::new(a.x.max(b.x), a.y.max(b.y)),
+        }
+    }
+
+    /// The whole canvas, or `None` if it has no pixels.
+    pub fn canvas(size: (u32, u32)) -> Option<Self> {
+        (size.0 > 0 && size.1 > 0).then(|| Self {
+            min: Position::new(0, 0),
+            max: Position::new(size.0 - 1, size.1 - 1),
+        })
+    }
+
+    /// Rectangle covered by the `pos` corners of a fill record.
+    pub fn from_fill(corners: (u64, u64), size: (u32, u32)) -> Result<Self, Error> {
+        Ok(Self::new(
+            Position::from_index(corners.0, size)?,
+            Position::from_index(corners.1, size)?,
+        ))
+    }
+
+    /// Corners for the `pos` field of a fill record.
+    pub fn to_fill(self, size: (u32, u32)) -> Result<(u64, u64), Error> {
+        Ok((self.min.to_index(size)?, self.max.to_index(size)?))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.max.x - self.min.x + 1
+    }
+
+    pub fn height(&self) -> u32 {
+        self.max.y - self.min.y + 1
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width() as u64 * self.height() as u64
+    }
+
+    pub fn contains(&self, pos: Position) -> bool {
+        (self.min.x..=self.max.x).contains(&pos.x) && (self.min.y..=self.max.y).contains(&pos.y)
+    }
+
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let min = Position::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y));
+        let max = Position::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y));
+        (min.x <= max.x && min.y <= max.y).then_some(Rect { min, max })
+    }
+
+    /// Positions in row-major order.
+    pub fn iter(&self) -> impl Iterator<Item = Position> + use<> {
+        let (xs, ys) = (self.min.x..=self.max.x, self.min.y..=self.max.y);
+        ys.flat_map(move |y| xs.clone().map(move |x| Position::new(x, y)))
+    }
+
+    /// Linear indices in row-major order on a canvas `width` pixels wide.
+    pub fn indices(&self, width: u32) -> impl Iterator<Item = u64> + use<> {
+        let width = width as u64;
+        self.iter()
+            .map(move |pos| pos.y as u64 * width + pos.x as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn position_index() {
+        let size = (4, 3);
+        assert_eq!(Position::from_index(6, size), Ok(Position::new(2, 1)));
+        assert_eq!(Position::new(2, 1).to_index(size), Ok(6));
+        assert_eq!(
+            Position::from_index(12, size),
+            Err(Error::IndexOutOfBounds { pos: 12, len: 12 })
+        );
+        assert_eq!(
+            Position::new(4, 0).to_index(size),
+            Err(Error::OutOfBounds {
+                pos: Position::new(4, 0),
+                size
+            })
+        );
+        assert!(Position::from_index(0, (0, 0)).is_err());
+    }
+
+    #[test]
+    fn canvas_pixel_count() {
+        assert_eq!(pixel_count((4, 3)), Ok(12));
+        assert_eq!(pixel_count((0, 3)), Ok(0));
+        assert_eq!(pixel_count((1 << 14, 1 << 14)), Ok(1 << 28));
+        assert_eq!(
+            pixel_count((u32::MAX, u32::MAX)),
+            Err(Error::TooLarge {
+                size: (u32::MAX, u32::MAX)
+            })
+        );
+    }
+
+    #[test]
+    fn rect_fill() {
+        let size = (4, 4);
+        // (2, 2) to (1, 0)
+        let rect = Rect::from_fill((10, 1), size).unwrap();
+
+        assert_eq!(rect.min, Position::new(1, 0));
+        assert_eq!(rect.max, Position::new(2, 2));
+        assert_eq!((rect.width(), rect.height(), rect.area()), (2, 3, 6));
+        assert_eq!(rect.to_fill(size), Ok((1, 10)));
+        assert_eq!(
+            rect.indices(size.0).collect::<Vec<_>>(),
+            vec![1, 2, 5, 6, 9, 10]
+        );
+        assert!(rect.contains(Position::new(2, 1)));
+        assert!(!rect.contains(Position::new(3, 1)));
+    }
+
+    #[test]
+    fn rect_intersection() {
+        let a = Rect::new(Position::new(0, 0), Position::new(3, 3));
+        let b = Rect::new(Position::new(2, 2), Position::new(5, 5));
+        let c = Rect::new(Position::new(4, 0), Position::new(5, 1));
+
+        assert_eq!(
+            a.intersection(&b),
+            Some(Rect::new(Position::new(2, 2), Position::new(3, 3)))
+        );
+        assert_eq!(a.intersection(&c), None);
+        assert_eq!(Rect::canvas((0, 5)), None);
+        assert_eq!(Rect::canvas((4, 4)).map(|r| r.area()), Some(16));
+    }
+}
@@ -0,0 +1,525 @@
+use std::{borrow::Borrow, fmt::Display, io::Write, num::NonZeroU32};
+
+use crate::{
+    CanvasRecord,
+    palette::{self, Palette},
+    position::{MAX_PIXELS, Rect},
+    state::{self, CanvasState},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    State(state::Error),
+    Palette(palette::Error),
+    Encoding(png::EncodingError),
+    /// An image above [`MAX_PIXELS`] once scaled by `scale`, or cropped to `size` with a scale
+    /// of 1.
+    TooLarge {
+        size: (u32, u32),
+        scale: u32,
+    },
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::State(e) => Some(e),
+            Error::Palette(e) => Some(e),
+            Error::Encoding(e) => Some(e),
+            Error::TooLarge { .. } => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::State(e) => write!(f, "cannot replay record: {e}"),
+            Error::Palette(e) => write!(f, "cannot replay record: {e}"),
+            Error::Encoding(e) => write!(f, "cannot encode png: {e}"),
+            Error::TooLarge { size, scale: 1 } => write!(
+                f,
+                "{}x{} image is larger than {MAX_PIXELS} pixels",
+                size.0, size.1
+            ),
+            Error::TooLarge { size, scale } => write!(
+                f,
+                "{}x{} image scaled by {scale} is larger than {MAX_PIXELS} pixels",
+                size.0, size.1
+            ),
+        }
+    }
+}
+
+impl From<state::Error> for Error {
+    fn from(value: state::Error) -> Self {
+        Error::State(value)
+    }
+}
+
+impl From<palette::Error> for Error {
+    fn from(value: palette::Error) -> Self {
+        Error::Palette(value)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(value: png::EncodingError) -> Self {
+        Error::Encoding(value)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// Color of pixels that are empty or whose color has since been removed.
+    pub background: [u8; 4],
+    /// Width and height in pixels of each canvas pixel.
+    pub scale: NonZeroU32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            background: [0x00; 4],
+            scale: NonZeroU32::MIN,
+        }
+    }
+}
+
+/// 8-bit RGBA image, stored row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_state(state: &CanvasState, palette: &Palette, background: [u8; 4]) -> Self {
+        let (width, height) = state.size();
+        let data = state
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                pixel
+                    .and_then(|col| palette.resolve(col))
+                    .unwrap_or(background)
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let start = (y as usize * self.width as usize + x as usize) * 4;
+        self.data[start..start + 4].try_into().ok()
+    }
+
+    /// Cut out `rect`, filling any part outside the image with `background`, or an error if
+    /// `rect` is above [`MAX_PIXELS`].
+    pub fn crop(&self, rect: Rect, background: [u8; 4]) -> Result<Self, Error> {
+        if rect.area() > MAX_PIXELS {
+            return Err(Error::TooLarge {
+                size: (rect.width(), rect.height()),
+                scale: 1,
+            });
+        }
+
+        let mut data = background.repeat(rect.area() as usize);
+        let bounds = Rect::canvas((self.width, self.height));
+        if let Some(inner) = bounds.and_then(|bounds| bounds.intersection(&rect)) {
+            let len = inner.width() as usize * 4;
+            for y in inner.min.y..=inner.max.y {
+                let src = (y as usize * self.width as usize + inner.min.x as usize) * 4;
+                let dst = ((y - rect.min.y) as usize * rect.width() as usize
+                    + (inner.min.x - rect.min.x) as usize)
+                    * 4;
+                data[dst..dst + len].copy_from_slice(&self.data[src..src + len]);
+            }
+        }
+
+        Ok(Self {
+            width: rect.width(),
+            height: rect.height(),
+            data,
+        })
+    }
+
+    /// Upscale by repeating every pixel `scale` times in both directions, or an error if the
+    /// scaled image is above [`MAX_PIXELS`].
+    pub fn scaled(&self, scale: NonZeroU32) -> Result<Self, Error> {
+        let too_large = || Error::TooLarge {
+            size: (self.width, self.height),
+            scale: scale.get(),
+        };
+        let width = self.width.checked_mul(scale.get()).ok_or_else(too_large)?;
+        let height = self.height.checked_mul(scale.get()).ok_or_else(too_large)?;
+        if width as u64 * height as u64 > MAX_PIXELS {
+            return Err(too_large());
+        }
+        let scale = scale.get() as usize;
+        if scale == 1 {
+            return Ok(self.clone());
+        }
+
+        let row_len = self.width as usize * 4;
+        if row_len == 0 || self.height == 0 {
+            return Ok(Self {
+                width,
+                height,
+                data: Vec::new(),
+            });
+        }
+
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in self.data.chunks_exact(row_len) {
+            let start = data.len();
+            for pixel in row.chunks_exact(4) {
+                for _ in 0..scale {
+                    data.extend_from_slice(pixel);
+                }
+            }
+            for _ in 1..scale {
+                data.extend_from_within(start..start + row_len * scale);
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Canvas and palette replayed together so placements can be resolved to colors.
+#[derive(Debug, Clone, Default)]
+pub struct Renderer {
+    state: CanvasState,
+    palette: Palette,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> &CanvasState {
+        &self.state
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Apply a record, rejecting placements outside the canvas or with an undefined color.
+    pub fn apply(&mut self, record: &CanvasRecord) -> Result<(), Error> {
+        // A rejected palette record changes nothing and placements only check the palette, so
+        // a rejected record leaves both untouched. A meta only clears the palette once the
+        // canvas accepts it.
+        match record {
+            CanvasRecord::CanvasMeta(_) => {
+                self.state.apply(record)?;
+                self.palette.apply(record)?;
+            }
+            _ => {
+                self.palette.apply(record)?;
+                self.state.apply(record)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn image(&self, options: &RenderOptions) -> Result<Image, Error> {
+        Image::from_state(&self.state, &self.palette, options.background).scaled(options.scale)
+    }
+}
+
+/// Render the canvas as it was at `time`.
+///
+/// Records are replayed in order until the first placement after `time`, so the stream must be
+/// sorted by time. Batches spanning `time` are applied up to and including `time`.
+pub fn render_at<R: Borrow<CanvasRecord>>(
+    records: impl IntoIterator<Item = R>,
+    time: u64,
+    options: &RenderOptions,
+) -> Result<Image, Error> {
+    let mut renderer = Renderer::new();
+    replay_until(&mut renderer, records, time)?;
+    renderer.image(options)
+}
+
+/// Replay the canvas as it was at `time`, starting from the last keyframe at or before it.
+///
+/// Records before that keyframe only have their meta, palette and identifier records applied,
+/// so only the placements after it are replayed. Like [`render_at`], the records must be sorted
+/// by time.
+pub fn seek<R: Borrow<CanvasRecord>>(records: &[R], time: u64) -> Result<Renderer, Error> {
+    let start = records
+        .iter()
+        .rposition(|record| {
+            matches!(record.borrow(), CanvasRecord::CanvasKeyframe(rec) if rec.time <= time)
+        })
+        .unwrap_or(0);
+
+    let mut renderer = Renderer::new();
+    for record in &records[..start] {
+        let record = record.borrow();
+        if matches!(
+            record,
+            CanvasRecord::CanvasMeta(_)
+                | CanvasRecord::PaletteInsert(_)
+                | CanvasRecord::PaletteRemove(_)
+                | CanvasRecord::IdentifierNumeric(_)
+                | CanvasRecord::IdentifierString(_)
+                | CanvasRecord::IdentifierSecret(_)
+        ) {
+            renderer.apply(record)?;
+        }
+    }
+
+    let tail = records[start..].iter().map(Borrow::borrow);
+    replay_until(&mut renderer, tail, time)?;
+    Ok(renderer)
+}
+
+fn replay_until<R: Borrow<CanvasRecord>>(
+    renderer: &mut Renderer,
+    records: impl IntoIterator<Item = R>,
+    time: u64,
+) -> Result<(), Error> {
+    for record in records {
+        let record = record.borrow();
+        match record_until(record, time) {
+            Until::Whole => renderer.apply(record)?,
+            Until::Partial(record) => {
+                renderer.apply(&record)?;
+                break;
+            }
+            Until::None => break,
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) enum Until {
+    Whole,
+    Partial(CanvasRecord),
+    None,
+}
+
+/// Part of `record` at or before `time`, splitting batches that span it.
+pub(crate) fn record_until(record: &CanvasRecord, time: u64) -> Until {
+    // Meta only sets up the canvas, so always apply it
+    if let CanvasRecord::CanvasMeta(_) = record {
+        return Until::Whole;
+    }
+
+    let len = record.times().take_while(|&t| t <= time).count();
+    if len == record.times().count() {
+        Until::Whole
+    } else if len == 0 {
+        Until::None
+    } else {
+        // Only batches have more than one time
+        Until::Partial(match record {
+            CanvasRecord::PlacementInsertBatch(batch) => {
+                CanvasRecord::PlacementInsertBatch(batch[..len].to_vec())
+            }
+            CanvasRecord::PlacementInsertBatchQuiet(batch) => {
+                CanvasRecord::PlacementInsertBatchQuiet(batch[..len].to_vec())
+            }
+            CanvasRecord::PlacementRemoveBatch(batch) => {
+                CanvasRecord::PlacementRemoveBatch(batch[..len].to_vec())
+            }
+            CanvasRecord::PlacementRemoveBatchQuiet(batch) => {
+                CanvasRecord::PlacementRemoveBatchQuiet(batch[..len].to_vec())
+            }
+            record => record.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        CanvasMeta, PaletteInsert, PlacementInsert, PlacementInsertFill, position::Position,
+    };
+
+    use super::*;
+
+    const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+    const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+    const BACKGROUND: [u8; 4] = [0x10, 0x20, 0x30, 0xFF];
+
+    fn records() -> Vec<CanvasRecord> {
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (3, 2),
+            }),
+            CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: 0,
+                colors: vec![WHITE, BLACK],
+            }),
+            CanvasRecord::PlacementInsertFill(PlacementInsertFill {
+                time: 10,
+                pos: (0, 5),
+                col: 0,
+            }),
+            CanvasRecord::PlacementInsertBatch(vec![
+                PlacementInsert {
+                    time: 20,
+                    pos: 0,
+                    col: 1,
+                },
+                PlacementInsert {
+                    time: 30,
+                    pos: 1,
+                    col: 1,
+                },
+            ]),
+        ]
+    }
+
+    #[test]
+    fn render_time() {
+        let options = RenderOptions {
+            background: BACKGROUND,
+            ..Default::default()
+        };
+
+        let image = render_at(records(), 5, &options).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.data, BACKGROUND.repeat(6));
+
+        let image = render_at(records(), 20, &options).unwrap();
+        assert_eq!(image.pixel(0, 0), Some(BLACK));
+        assert_eq!(image.pixel(1, 0), Some(WHITE));
+        assert_eq!(image.pixel(2, 1), Some(WHITE));
+        assert_eq!(image.pixel(3, 0), None);
+
+        let image = render_at(records(), u64::MAX, &options).unwrap();
+        assert_eq!(image.pixel(1, 0), Some(BLACK));
+    }
+
+    #[test]
+    fn render_scaled() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            data: [WHITE, BLACK].concat(),
+        };
+        let scaled = image.scaled(NonZeroU32::new(2).unwrap()).unwrap();
+
+        assert_eq!((scaled.width, scaled.height), (4, 2));
+        assert_eq!(scaled.data, [WHITE, WHITE, BLACK, BLACK].concat().repeat(2));
+
+        let empty = Image {
+            width: 0,
+            height: 3,
+            data: Vec::new(),
+        };
+        let scaled = empty.scaled(NonZeroU32::new(2).unwrap()).unwrap();
+        assert_eq!((scaled.width, scaled.height), (0, 6));
+        assert!(scaled.data.is_empty());
+
+        assert!(matches!(
+            image.scaled(NonZeroU32::MAX),
+            Err(Error::TooLarge {
+                size: (2, 1),
+                scale: u32::MAX
+            })
+        ));
+        // No overflow, but far too many pixels to allocate
+        let canvas = Image {
+            width: 2000,
+            height: 2000,
+            data: Vec::new(),
+        };
+        assert!(matches!(
+            canvas.scaled(NonZeroU32::new(4096).unwrap()),
+            Err(Error::TooLarge {
+                size: (2000, 2000),
+                scale: 4096
+            })
+        ));
+    }
+
+    #[test]
+    fn render_crop() {
+        let image = Image {
+            width: 2,
+            height: 2,
+            data: [WHITE, BLACK, BLACK, WHITE].concat(),
+        };
+        let cropped = image.crop(
+            Rect::new(Position::new(1, 1), Position::new(2, 1)),
+            BACKGROUND,
+        );
+
+        let cropped = cropped.unwrap();
+        assert_eq!((cropped.width, cropped.height), (2, 1));
+        assert_eq!(cropped.data, [WHITE, BACKGROUND].concat());
+
+        let huge = Rect::new(Position::new(0, 0), Position::new(1 << 15, 1 << 15));
+        assert!(matches!(
+            image.crop(huge, BACKGROUND),
+            Err(Error::TooLarge { scale: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn render_png() {
+        let image = render_at(records(), u64::MAX, &RenderOptions::default()).unwrap();
+        let mut buf = Vec::new();
+        image.write_png(&mut buf).unwrap();
+
+        let mut reader = png::Decoder::new(buf.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(data, image.data);
+    }
+
+    #[test]
+    fn render_undefined_color() {
+        let mut records = records();
+        records.push(CanvasRecord::PlacementInsert(PlacementInsert {
+            time: 40,
+            pos: 0,
+            col: 5,
+        }));
+
+        let err = render_at(&records, u64::MAX, &RenderOptions::default())
+            .expect_err("rendered undefined color");
+        assert!(matches!(
+            err,
+            Error::Palette(palette::Error::UndefinedColor(5))
+        ));
+    }
+}
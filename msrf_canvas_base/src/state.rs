@@ -0,0 +1,508 @@
+use crate::{
+    CanvasKeyframe, CanvasMeta, CanvasRecord, Identifier,
+    position::{self, Position, Rect},
+};
+
+pub use crate::position::Error;
+
+/// A single pixel written by a record.
+///
+/// Every pixel touched by a record is reported, so `previous` may equal `current` when a
+/// placement repeats the existing colour.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Change {
+    pub pos: u64,
+    pub previous: Option<u32>,
+    pub current: Option<u32>,
+}
+
+/// Every pixel written by one record and who wrote them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Changes {
+    /// [`CanvasState::author`] when the record was applied, `None` if the record wrote no pixels.
+    pub author: Option<Identifier>,
+    pub pixels: Vec<Change>,
+}
+
+/// Canvas contents produced by replaying a stream of [`CanvasRecord`]s.
+///
+/// Each pixel holds the palette index of the last placement, or `None` if it is empty (never
+/// placed or removed). Fills cover the inclusive rectangle spanned by their two corners.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CanvasState {
+    size: (u32, u32),
+    time: u64,
+    author: Option<Identifier>,
+    pixels: Vec<Option<u32>>,
+}
+
+impl CanvasState {
+    /// Empty canvas of `size`, or an error if it is above [`MAX_PIXELS`](position::MAX_PIXELS).
+    pub fn new(size: (u32, u32)) -> Result<Self, Error> {
+        Ok(Self {
+            size,
+            time: 0,
+            author: None,
+            pixels: vec![None; position::pixel_count(size)?],
+        })
+    }
+
+    pub fn from_meta(meta: &CanvasMeta) -> Result<Self, Error> {
+        Ok(Self {
+            time: meta.time,
+            ..Self::new(meta.size)?
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Time of the most recently applied timestamped record.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Snapshot of the pixels as a keyframe record.
+    pub fn keyframe(&self) -> CanvasKeyframe {
+        CanvasKeyframe {
+            time: self.time,
+            size: self.size,
+            pixels: self.pixels.clone(),
+        }
+    }
+
+    /// Author of placements applied from now on, set by the most recent identifier record.
+    ///
+    /// The changes returned by [`apply`](Self::apply) for a placement carry the author at that
+    /// point.
+    pub fn author(&self) -> Option<&Identifier> {
+        self.author.as_ref()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.pixels.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    pub fn pixels(&self) -> &[Option<u32>] {
+        &self.pixels
+    }
+
+    pub fn get(&self, pos: u64) -> Option<u32> {
+        self.pixels.get(pos as usize).copied().flatten()
+    }
+
+    /// Pixel at `pos`, or `None` if it is empty or outside the canvas.
+    pub fn get_at(&self, pos: Position) -> Option<u32> {
+        pos.to_index(self.size).ok().and_then(|pos| self.get(pos))
+    }
+
+    /// Apply a record, returning every pixel it wrote and its author.
+    ///
+    /// A [`CanvasMeta`] (re)initialises the canvas to an empty buffer of its size and clears the
+    /// [`author`](Self::author), which identifier records set. A
+    /// [`CanvasResize`](crate::CanvasResize) moves existing pixels into the new size and reports
+    /// no changes, as positions before and after it index different canvases; records following
+    /// it are interpreted against the new size. A [`CanvasKeyframe`] replaces every pixel and also
+    /// reports no changes. Palette records are accepted and change nothing.
+    /// On error the state is left untouched.
+    pub fn apply(&mut self, record: &CanvasRecord) -> Result<Changes, Error> {
+        match record {
+            CanvasRecord::CanvasMeta(meta) => {
+                *self = Self::from_meta(meta)?;
+                Ok(Changes::default())
+            }
+            CanvasRecord::CanvasResize(rec) => {
+                let mut resized = Self::new(rec.size)?;
+                for (pos, pixel) in self.pixels.iter().enumerate() {
+                    if let Some(pos) = rec.remap_index(pos as u64, self.size) {
+                        resized.pixels[pos as usize] = *pixel;
+                    }
+                }
+
+                resized.time = rec.time;
+                resized.author = self.author.take();
+                *self = resized;
+                Ok(Changes::default())
+            }
+            CanvasRecord::CanvasKeyframe(rec) => {
+                let len = rec.pixels.len() as u64;
+                if len != rec.size.0 as u64 * rec.size.1 as u64 {
+                    return Err(Error::SizeMismatch {
+                        len,
+                        size: rec.size,
+                    });
+                }
+
+                self.size = rec.size;
+                self.time = rec.time;
+                self.pixels.clone_from(&rec.pixels);
+                Ok(Changes::default())
+            }
+            CanvasRecord::PlacementInsert(rec) | CanvasRecord::PlacementInsertQuiet(rec) => {
+                self.check(rec.pos)?;
+                self.time = rec.time;
+                let pixels = vec![self.set(rec.pos, Some(rec.col))];
+                Ok(self.changes(pixels))
+            }
+            CanvasRecord::PlacementInsertFill(rec)
+            | CanvasRecord::PlacementInsertFillQuiet(rec) => {
+                let positions = self.fill_positions(rec.pos)?;
+                self.time = rec.time;
+                let pixels = positions.map(|pos| self.set(pos, Some(rec.col))).collect();
+                Ok(self.changes(pixels))
+            }
+            CanvasRecord::PlacementRemove(rec) | CanvasRecord::PlacementRemoveQuiet(rec) => {
+                self.check(rec.pos)?;
+                self.time = rec.time;
+                let pixels = vec![self.set(rec.pos, None)];
+                Ok(self.changes(pixels))
+            }
+            CanvasRecord::PlacementRemoveFill(rec)
+            | CanvasRecord::PlacementRemoveFillQuiet(rec) => {
+                let positions = self.fill_positions(rec.pos)?;
+                self.time = rec.time;
+                let pixels = positions.map(|pos| self.set(pos, None)).collect();
+                Ok(self.changes(pixels))
+            }
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => {
+                batch.iter().try_for_each(|rec| self.check(rec.pos))?;
+                self.time = batch.last().map_or(self.time, |rec| rec.time);
+                let pixels = batch
+                    .iter()
+                    .map(|rec| self.set(rec.pos, Some(rec.col)))
+                    .collect();
+                Ok(self.changes(pixels))
+            }
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => {
+                batch.iter().try_for_each(|rec| self.check(rec.pos))?;
+                self.time = batch.last().map_or(self.time, |rec| rec.time);
+                let pixels = batch.iter().map(|rec| self.set(rec.pos, None)).collect();
+                Ok(self.changes(pixels))
+            }
+            CanvasRecord::IdentifierNumeric(_)
+            | CanvasRecord::IdentifierString(_)
+            | CanvasRecord::IdentifierSecret(_) => {
+                self.author = record.identifier();
+                Ok(Changes::default())
+            }
+            CanvasRecord::PaletteInsert(_) | CanvasRecord::PaletteRemove(_) => {
+                Ok(Changes::default())
+            }
+        }
+    }
+
+    fn check(&self, pos: u64) -> Result<(), Error> {
+        if pos < self.len() {
+            Ok(())
+        } else {
+            Err(Error::IndexOutOfBounds {
+                pos,
+                len: self.len(),
+            })
+        }
+    }
+
+    fn set(&mut self, pos: u64, current: Option<u32>) -> Change {
+        let previous = std::mem::replace(&mut self.pixels[pos as usize], current);
+        Change {
+            pos,
+            previous,
+            current,
+        }
+    }
+
+    fn changes(&self, pixels: Vec<Change>) -> Changes {
+        Changes {
+            author: self.author.clone(),
+            pixels,
+        }
+    }
+
+    fn fill_positions(
+        &self,
+        corners: (u64, u64),
+    ) -> Result<impl Iterator<Item = u64> + use<>, Error> {
+        let rect = Rect::from_fill(corners, self.size)?;
+        Ok(rect.indices(self.size.0))
+    }
+}
+
+/// Time of each [`Change`] returned by [`CanvasState::apply`] for a placement record, in order.
+pub(crate) fn change_times(record: &CanvasRecord) -> impl Iterator<Item = Option<u64>> + '_ {
+    // Batches have a time per change, while every pixel of a fill shares the record's time
+    record
+        .times()
+        .map(Some)
+        .chain(std::iter::repeat(record.time()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        CanvasResize, PaletteInsert, PlacementInsert, PlacementInsertFill, PlacementRemove,
+    };
+
+    use super::*;
+
+    #[test]
+    fn apply_placement() {
+        let mut state = CanvasState::new((4, 4)).unwrap();
+        let changes = state
+            .apply(&CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 10,
+                pos: 5,
+                col: 3,
+            }))
+            .unwrap();
+
+        assert_eq!(
+            changes.pixels,
+            vec![Change {
+                pos: 5,
+                previous: None,
+                current: Some(3),
+            }]
+        );
+        assert_eq!(state.get(5), Some(3));
+        assert_eq!(state.time(), 10);
+
+        let changes = state
+            .apply(&CanvasRecord::PlacementRemoveQuiet(PlacementRemove {
+                time: 11,
+                pos: 5,
+            }))
+            .unwrap();
+
+        assert_eq!(changes.pixels[0].previous, Some(3));
+        assert_eq!(state.get(5), None);
+    }
+
+    #[test]
+    fn apply_fill() {
+        let mut state = CanvasState::new((4, 4)).unwrap();
+        // Corners are given in reverse order: (2, 2) to (1, 0)
+        let changes = state
+            .apply(&CanvasRecord::PlacementInsertFill(PlacementInsertFill {
+                time: 10,
+                pos: (10, 1),
+                col: 7,
+            }))
+            .unwrap();
+
+        let positions: Vec<u64> = changes.pixels.iter().map(|c| c.pos).collect();
+        assert_eq!(positions, vec![1, 2, 5, 6, 9, 10]);
+        assert_eq!(state.pixels().iter().filter(|p| p.is_some()).count(), 6);
+    }
+
+    #[test]
+    fn apply_out_of_bounds() {
+        let mut state = CanvasState::new((4, 4)).unwrap();
+        let err = state
+            .apply(&CanvasRecord::PlacementInsertFill(PlacementInsertFill {
+                time: 10,
+                pos: (0, 16),
+                col: 7,
+            }))
+            .expect_err("applied out of bounds fill");
+
+        assert_eq!(err, Error::IndexOutOfBounds { pos: 16, len: 16 });
+        assert_eq!(state, CanvasState::new((4, 4)).unwrap());
+    }
+
+    #[test]
+    fn apply_batch() {
+        let mut state = CanvasState::new((4, 4)).unwrap();
+        let batch = vec![
+            PlacementInsert {
+                time: 10,
+                pos: 0,
+                col: 1,
+            },
+            PlacementInsert {
+                time: 11,
+                pos: 0,
+                col: 2,
+            },
+            PlacementInsert {
+                time: 12,
+                pos: 15,
+                col: 3,
+            },
+        ];
+        let changes = state
+            .apply(&CanvasRecord::PlacementInsertBatch(batch))
+            .unwrap();
+
+        assert_eq!(changes.pixels.len(), 3);
+        assert_eq!(changes.pixels[1].previous, Some(1));
+        assert_eq!(state.get(0), Some(2));
+        assert_eq!(state.get(15), Some(3));
+        assert_eq!(state.time(), 12);
+
+        // A single bad entry rejects the whole batch
+        let batch = vec![
+            PlacementRemove { time: 13, pos: 0 },
+            PlacementRemove { time: 14, pos: 16 },
+        ];
+        state
+            .apply(&CanvasRecord::PlacementRemoveBatchQuiet(batch))
+            .expect_err("applied out of bounds batch");
+
+        assert_eq!(state.get(0), Some(2));
+    }
+
+    #[test]
+    fn apply_meta() {
+        let mut state = CanvasState::default();
+        state
+            .apply(&CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 1234,
+                size: (3, 2),
+            }))
+            .unwrap();
+
+        assert_eq!(state.size(), (3, 2));
+        assert_eq!(state.len(), 6);
+        assert_eq!(state.time(), 1234);
+
+        let changes = state
+            .apply(&CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: 0,
+                colors: vec![[0xFF; 4]],
+            }))
+            .unwrap();
+
+        assert_eq!(changes, Changes::default());
+
+        // Metadata too large to allocate is rejected rather than aborting
+        let size = (u32::MAX, u32::MAX);
+        let huge = CanvasRecord::CanvasMeta(CanvasMeta {
+            name: "huge".to_string(),
+            platform: "pxls.space".to_string(),
+            time: 0,
+            size,
+        });
+        assert_eq!(state.apply(&huge), Err(Error::TooLarge { size }));
+        assert_eq!(state.size(), (3, 2));
+    }
+
+    #[test]
+    fn apply_resize() {
+        let mut state = CanvasState::new((2, 2)).unwrap();
+        for (pos, col) in [(0, 1), (3, 2)] {
+            state
+                .apply(&CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: 1,
+                    pos,
+                    col,
+                }))
+                .unwrap();
+        }
+
+        // Grow to the left, then shrink away the right column
+        let resize =
+            |time, size, offset| CanvasRecord::CanvasResize(CanvasResize { time, size, offset });
+        assert_eq!(
+            state.apply(&resize(5, (3, 2), (1, 0))),
+            Ok(Changes::default())
+        );
+        assert_eq!(state.size(), (3, 2));
+        assert_eq!(state.time(), 5);
+        assert_eq!(state.pixels(), &[None, Some(1), None, None, None, Some(2)]);
+
+        state.apply(&resize(6, (2, 2), (0, 0))).unwrap();
+        assert_eq!(state.get_at(Position::new(1, 0)), Some(1));
+        assert_eq!(state.pixels(), &[None, Some(1), None, None]);
+
+        // Positions after the resize use the new size
+        assert!(
+            state
+                .apply(&CanvasRecord::PlacementInsert(PlacementInsert {
+                    time: 7,
+                    pos: 4,
+                    col: 0
+                }))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn apply_author() {
+        let place = |pos| {
+            CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 1,
+                pos,
+                col: 0,
+            })
+        };
+        let mut state = CanvasState::new((2, 2)).unwrap();
+        assert_eq!(state.apply(&place(0)).unwrap().author, None);
+        assert_eq!(state.author(), None);
+
+        let changes = state
+            .apply(&CanvasRecord::IdentifierString("user".to_string()))
+            .unwrap();
+        assert_eq!(changes, Changes::default());
+
+        let changes = state.apply(&place(1)).unwrap();
+        let user = Identifier::String("user".to_string());
+        assert_eq!(changes.author, Some(user.clone()));
+        assert_eq!(state.author(), Some(&user));
+
+        state.apply(&Identifier::Numerical(7).into()).unwrap();
+        assert_eq!(state.author(), Some(&Identifier::Numerical(7)));
+
+        state
+            .apply(&CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (2, 2),
+            }))
+            .unwrap();
+        assert_eq!(state.author(), None);
+    }
+
+    #[test]
+    fn apply_keyframe() {
+        let mut state = CanvasState::new((2, 2)).unwrap();
+        state
+            .apply(&CanvasRecord::PlacementInsert(PlacementInsert {
+                time: 3,
+                pos: 2,
+                col: 1,
+            }))
+            .unwrap();
+
+        let keyframe = state.keyframe();
+        assert_eq!(keyframe.pixels, vec![None, None, Some(1), None]);
+
+        let mut restored = CanvasState::new((1, 1)).unwrap();
+        assert_eq!(
+            restored.apply(&keyframe.clone().into()),
+            Ok(Changes::default())
+        );
+        assert_eq!(restored, state);
+
+        let invalid = CanvasKeyframe {
+            size: (3, 2),
+            ..keyframe
+        };
+        assert_eq!(
+            restored.apply(&invalid.into()),
+            Err(Error::SizeMismatch {
+                len: 4,
+                size: (3, 2)
+            })
+        );
+    }
+}
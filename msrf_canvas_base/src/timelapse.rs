@@ -0,0 +1,375 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    io::Write,
+    num::{NonZeroU32, NonZeroU64},
+};
+
+use crate::{
+    CanvasRecord,
+    position::Rect,
+    render::{self, Image, RenderOptions, Renderer},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Render(render::Error),
+    FrameSize {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    NoFrames,
+    /// More frames than an APNG can count.
+    TooManyFrames(usize),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Render(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Render(e) => write!(f, "{e}"),
+            Error::FrameSize { expected, actual } => write!(
+                f,
+                "frame is {}x{} but animation is {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            Error::NoFrames => write!(f, "animation has no frames"),
+            Error::TooManyFrames(len) => {
+                write!(f, "animation has {len} frames, more than {}", u32::MAX)
+            }
+        }
+    }
+}
+
+impl From<render::Error> for Error {
+    fn from(value: render::Error) -> Self {
+        Error::Render(value)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(value: png::EncodingError) -> Self {
+        Error::Render(render::Error::Encoding(value))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interval {
+    /// Frame every given span of simulated time.
+    Time(NonZeroU64),
+    /// Frame every given number of placements, counting each fill and batch entry once.
+    Placements(NonZeroU64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimelapseOptions {
+    pub interval: Interval,
+    /// Region of the canvas to keep, applied before scaling.
+    pub crop: Option<Rect>,
+    pub render: RenderOptions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Time the frame shows the canvas at.
+    pub time: u64,
+    pub image: Image,
+}
+
+/// Iterator replaying records and yielding a [`Frame`] at every interval.
+///
+/// A final frame is yielded for anything applied after the last interval. Replay stops at the
+/// first record that fails to apply. Frames follow any [`CanvasResize`](crate::CanvasResize)
+/// in the stream, so set a [`crop`](TimelapseOptions::crop) to keep every frame the same size.
+#[derive(Debug)]
+pub struct Timelapse<I> {
+    records: I,
+    options: TimelapseOptions,
+    renderer: Renderer,
+    // Batch entries and the record that ended the previous frame
+    pending: VecDeque<CanvasRecord>,
+    next_time: Option<u64>,
+    placements: u64,
+    dirty: bool,
+    done: bool,
+}
+
+impl<I: Iterator<Item = CanvasRecord>> Timelapse<I> {
+    pub fn new(records: impl IntoIterator<IntoIter = I>, options: TimelapseOptions) -> Self {
+        Self {
+            records: records.into_iter(),
+            options,
+            renderer: Renderer::new(),
+            pending: VecDeque::new(),
+            next_time: None,
+            placements: 0,
+            dirty: false,
+            done: false,
+        }
+    }
+
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    fn next_record(&mut self) -> Option<CanvasRecord> {
+        if let Some(record) = self.pending.pop_front() {
+            return Some(record);
+        }
+
+        // Split batches so frames can fall between their entries
+        match self.records.next()? {
+            CanvasRecord::PlacementInsertBatch(batch) => self
+                .pending
+                .extend(batch.into_iter().map(CanvasRecord::PlacementInsert)),
+            CanvasRecord::PlacementInsertBatchQuiet(batch) => self
+                .pending
+                .extend(batch.into_iter().map(CanvasRecord::PlacementInsertQuiet)),
+            CanvasRecord::PlacementRemoveBatch(batch) => self
+                .pending
+                .extend(batch.into_iter().map(CanvasRecord::PlacementRemove)),
+            CanvasRecord::PlacementRemoveBatchQuiet(batch) => self
+                .pending
+                .extend(batch.into_iter().map(CanvasRecord::PlacementRemoveQuiet)),
+            record => return Some(record),
+        }
+
+        self.next_record()
+    }
+
+    fn frame(&mut self, time: u64) -> Result<Frame, Error> {
+        self.dirty = false;
+
+        let background = self.options.render.background;
+        let state = self.renderer.state();
+        let mut image = Image::from_state(state, self.renderer.palette(), background);
+        if let Some(rect) = self.options.crop {
+            image = image.crop(rect, background)?;
+        }
+
+        Ok(Frame {
+            time,
+            image: image.scaled(self.options.render.scale)?,
+        })
+    }
+}
+
+impl<I: Iterator<Item = CanvasRecord>> Iterator for Timelapse<I> {
+    type Item = Result<Frame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let Some(record) = self.next_record() else {
+                self.done = true;
+                let time = self.renderer.state().time();
+                return self.dirty.then(|| self.frame(time));
+            };
+
+            if let Interval::Time(step) = self.options.interval
+                && let Some(time) = record.time()
+            {
+                let next = *self
+                    .next_time
+                    .get_or_insert(time.saturating_add(step.get()));
+                if time > next {
+                    self.pending.push_front(record);
+                    self.next_time = Some(next.saturating_add(step.get()));
+                    return Some(self.frame(next));
+                }
+            }
+
+            if let Err(e) = self.renderer.apply(&record) {
+                self.done = true;
+                return Some(Err(e.into()));
+            }
+            self.dirty = true;
+
+            if let Interval::Placements(count) = self.options.interval
+                && let Some(time) = record.time()
+                && !matches!(
+                    record,
+                    CanvasRecord::CanvasMeta(_)
+                        | CanvasRecord::CanvasResize(_)
+                        | CanvasRecord::CanvasKeyframe(_)
+                )
+            {
+                self.placements += 1;
+                if self.placements.is_multiple_of(count.get()) {
+                    return Some(self.frame(time));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Encoder for an animated PNG of equally sized frames.
+///
+/// APNG stores the frame count in its header, so it must be known before the first frame.
+pub struct ApngWriter<W: Write> {
+    writer: png::Writer<W>,
+    size: (u32, u32),
+}
+
+impl<W: Write> ApngWriter<W> {
+    /// Start an animation showing each frame for `delay_ms` milliseconds, looping forever.
+    pub fn new(
+        writer: W,
+        size: (u32, u32),
+        frames: NonZeroU32,
+        delay_ms: u16,
+    ) -> Result<Self, Error> {
+        let mut encoder = png::Encoder::new(writer, size.0, size.1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.get(), 0)?;
+        encoder.set_frame_delay(delay_ms, 1000)?;
+
+        Ok(Self {
+            writer: encoder.write_header()?,
+            size,
+        })
+    }
+
+    pub fn write_frame(&mut self, image: &Image) -> Result<(), Error> {
+        let actual = (image.width, image.height);
+        if actual != self.size {
+            return Err(Error::FrameSize {
+                expected: self.size,
+                actual,
+            });
+        }
+
+        self.writer.write_image_data(&image.data)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Write `frames` as an animated PNG sized to the first frame.
+pub fn write_apng<W: Write>(writer: W, frames: &[Frame], delay_ms: u16) -> Result<(), Error> {
+    let first = frames.first().ok_or(Error::NoFrames)?;
+    let count = u32::try_from(frames.len()).map_err(|_| Error::TooManyFrames(frames.len()))?;
+    let count = NonZeroU32::new(count).ok_or(Error::NoFrames)?;
+    let size = (first.image.width, first.image.height);
+
+    let mut apng = ApngWriter::new(writer, size, count, delay_ms)?;
+    for frame in frames {
+        apng.write_frame(&frame.image)?;
+    }
+
+    apng.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CanvasMeta, PaletteInsert, PlacementInsert, position::Position};
+
+    use super::*;
+
+    const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+    fn records() -> Vec<CanvasRecord> {
+        let place = |time, pos| PlacementInsert { time, pos, col: 0 };
+        vec![
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (2, 2),
+            }),
+            CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: 0,
+                colors: vec![WHITE],
+            }),
+            CanvasRecord::PlacementInsert(place(5, 0)),
+            CanvasRecord::PlacementInsertBatch(vec![place(8, 1), place(12, 2)]),
+            CanvasRecord::PlacementInsert(place(35, 3)),
+        ]
+    }
+
+    fn options(interval: Interval) -> TimelapseOptions {
+        TimelapseOptions {
+            interval,
+            crop: None,
+            render: RenderOptions::default(),
+        }
+    }
+
+    fn placed(frame: &Frame) -> usize {
+        frame
+            .image
+            .data
+            .chunks_exact(4)
+            .filter(|pixel| *pixel == WHITE)
+            .count()
+    }
+
+    #[test]
+    fn timelapse_time() {
+        let interval = Interval::Time(NonZeroU64::new(10).unwrap());
+        let frames: Vec<Frame> = Timelapse::new(records(), options(interval))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let summary: Vec<(u64, usize)> = frames.iter().map(|f| (f.time, placed(f))).collect();
+        assert_eq!(summary, vec![(10, 2), (20, 3), (30, 3), (35, 4)]);
+    }
+
+    #[test]
+    fn timelapse_placements() {
+        let interval = Interval::Placements(NonZeroU64::new(2).unwrap());
+        let frames: Vec<Frame> = Timelapse::new(records(), options(interval))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let summary: Vec<(u64, usize)> = frames.iter().map(|f| (f.time, placed(f))).collect();
+        assert_eq!(summary, vec![(8, 2), (35, 4)]);
+    }
+
+    #[test]
+    fn timelapse_crop_apng() {
+        let options = TimelapseOptions {
+            crop: Some(Rect::new(Position::new(1, 0), Position::new(1, 1))),
+            render: RenderOptions {
+                scale: NonZeroU32::new(3).unwrap(),
+                ..Default::default()
+            },
+            ..options(Interval::Placements(NonZeroU64::MIN))
+        };
+        let frames: Vec<Frame> = Timelapse::new(records(), options)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(frames.len(), 4);
+        assert!(
+            frames
+                .iter()
+                .all(|f| (f.image.width, f.image.height) == (3, 6))
+        );
+
+        let mut buf = Vec::new();
+        write_apng(&mut buf, &frames, 100).unwrap();
+
+        let reader = png::Decoder::new(buf.as_slice()).read_info().unwrap();
+        let animation = reader.info().animation_control().unwrap();
+        assert_eq!(animation.num_frames, 4);
+        assert!(matches!(
+            write_apng(Vec::new(), &[], 100),
+            Err(Error::NoFrames)
+        ));
+    }
+}
@@ -0,0 +1,494 @@
+use std::fmt::Display;
+
+use crate::{
+    CanvasRecord,
+    palette::{self, MAX_COLORS, Palette},
+    position::{self, MAX_PIXELS},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// The stream does not start with a `CanvasMeta`.
+    MissingMeta,
+    /// A `CanvasMeta` after the first record, resetting the canvas.
+    RepeatedMeta,
+    OutOfBounds {
+        pos: u64,
+        len: u64,
+    },
+    UndefinedColor(u32),
+    RemovedColor(u32),
+    TimeReversed {
+        time: u64,
+        previous: u64,
+    },
+    EmptyBatch,
+    /// A keyframe whose pixels do not fill its canvas size.
+    KeyframeSize {
+        len: u64,
+        size: (u32, u32),
+    },
+    /// A palette insert reaching past [`MAX_COLORS`].
+    PaletteTooLarge {
+        offset: u32,
+        len: usize,
+    },
+    /// A canvas size above [`MAX_PIXELS`].
+    CanvasTooLarge {
+        size: (u32, u32),
+    },
+}
+
+impl Issue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Issue::RepeatedMeta | Issue::TimeReversed { .. } | Issue::EmptyBatch => {
+                Severity::Warning
+            }
+            Issue::MissingMeta
+            | Issue::OutOfBounds { .. }
+            | Issue::UndefinedColor(_)
+            | Issue::RemovedColor(_)
+            | Issue::KeyframeSize { .. }
+            | Issue::PaletteTooLarge { .. }
+            | Issue::CanvasTooLarge { .. } => Severity::Error,
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::MissingMeta => write!(f, "stream does not start with canvas meta"),
+            Issue::RepeatedMeta => write!(f, "canvas meta repeated mid-stream"),
+            Issue::OutOfBounds { pos, len } => {
+                write!(f, "position {pos} out of bounds (canvas has {len} pixels)")
+            }
+            Issue::UndefinedColor(col) => write!(f, "color {col} was never defined"),
+            Issue::RemovedColor(col) => write!(f, "color {col} was removed"),
+            Issue::TimeReversed { time, previous } => {
+                write!(f, "time {time} is before previous time {previous}")
+            }
+            Issue::EmptyBatch => write!(f, "batch contains no placements"),
+            Issue::KeyframeSize { len, size } => write!(
+                f,
+                "keyframe has {len} pixels for a {}x{} canvas",
+                size.0, size.1
+            ),
+            Issue::PaletteTooLarge { offset, len } => write!(
+                f,
+                "{len} colors at offset {offset} exceed the {MAX_COLORS} palette slots"
+            ),
+            Issue::CanvasTooLarge { size } => write!(
+                f,
+                "{}x{} canvas is larger than {MAX_PIXELS} pixels",
+                size.0, size.1
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Index of the offending record within the stream.
+    pub index: usize,
+    pub severity: Severity,
+    pub issue: Issue,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: record {}: {}", self.index, self.issue)
+    }
+}
+
+/// Incremental checker for the consistency of a record stream.
+///
+/// Bounds are only checked once a `CanvasMeta` has defined the canvas size, so a stream
+/// missing its meta reports [`Issue::MissingMeta`] once rather than every placement.
+///
+/// The canvas is checked arithmetically rather than replayed, so hostile sizes are reported
+/// without allocating for them. The palette is replayed as in [`Palette::apply`].
+#[derive(Debug, Default)]
+pub struct Validator {
+    index: usize,
+    has_meta: bool,
+    time: Option<u64>,
+    size: (u32, u32),
+    palette: Palette,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, record: &CanvasRecord) {
+        if let CanvasRecord::CanvasMeta(_) = record {
+            if self.index > 0 {
+                self.report(Issue::RepeatedMeta);
+            }
+            self.has_meta = true;
+        } else if self.index == 0 {
+            self.report(Issue::MissingMeta);
+        }
+
+        self.check_time(record);
+        self.check_batch(record);
+        self.check_palette(record);
+        if self.has_meta {
+            self.check_bounds(record);
+        }
+
+        self.index += 1;
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn finish(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    fn report(&mut self, issue: Issue) {
+        self.diagnostics.push(Diagnostic {
+            index: self.index,
+            severity: issue.severity(),
+            issue,
+        });
+    }
+
+    fn check_time(&mut self, record: &CanvasRecord) {
+        for time in record.times() {
+            if let Some(previous) = self.time
+                && time < previous
+            {
+                self.report(Issue::TimeReversed { time, previous });
+            }
+            self.time = Some(time);
+        }
+    }
+
+    fn check_batch(&mut self, record: &CanvasRecord) {
+        let empty = match record {
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => batch.is_empty(),
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => batch.is_empty(),
+            _ => false,
+        };
+
+        if empty {
+            self.report(Issue::EmptyBatch);
+        }
+    }
+
+    fn check_palette(&mut self, record: &CanvasRecord) {
+        // Report every bad color in a batch, not only the first
+        let cols: Vec<u32> = match record {
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => {
+                batch.iter().map(|rec| rec.col).collect()
+            }
+            CanvasRecord::PlacementInsert(rec) | CanvasRecord::PlacementInsertQuiet(rec) => {
+                vec![rec.col]
+            }
+            CanvasRecord::PlacementInsertFill(rec)
+            | CanvasRecord::PlacementInsertFillQuiet(rec) => vec![rec.col],
+            CanvasRecord::CanvasMeta(_)
+            | CanvasRecord::PaletteInsert(_)
+            | CanvasRecord::PaletteRemove(_) => {
+                if let Err(e) = self.palette.apply(record) {
+                    self.report(palette_issue(e));
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        for col in cols {
+            if let Err(e) = self.palette.check(col) {
+                self.report(palette_issue(e));
+            }
+        }
+    }
+
+    fn check_bounds(&mut self, record: &CanvasRecord) {
+        // Check every position, including both corners of a fill, to report every bad one
+        let positions: Vec<u64> = match record {
+            CanvasRecord::PlacementInsertBatch(batch)
+            | CanvasRecord::PlacementInsertBatchQuiet(batch) => {
+                batch.iter().map(|rec| rec.pos).collect()
+            }
+            CanvasRecord::PlacementRemoveBatch(batch)
+            | CanvasRecord::PlacementRemoveBatchQuiet(batch) => {
+                batch.iter().map(|rec| rec.pos).collect()
+            }
+            CanvasRecord::PlacementInsert(rec) | CanvasRecord::PlacementInsertQuiet(rec) => {
+                vec![rec.pos]
+            }
+            CanvasRecord::PlacementRemove(rec) | CanvasRecord::PlacementRemoveQuiet(rec) => {
+                vec![rec.pos]
+            }
+            CanvasRecord::PlacementInsertFill(rec)
+            | CanvasRecord::PlacementInsertFillQuiet(rec) => vec![rec.pos.0, rec.pos.1],
+            CanvasRecord::PlacementRemoveFill(rec)
+            | CanvasRecord::PlacementRemoveFillQuiet(rec) => vec![rec.pos.0, rec.pos.1],
+            CanvasRecord::CanvasMeta(rec) => {
+                self.resize(rec.size);
+                return;
+            }
+            CanvasRecord::CanvasResize(rec) => {
+                self.resize(rec.size);
+                return;
+            }
+            CanvasRecord::CanvasKeyframe(rec) => {
+                let len = rec.pixels.len() as u64;
+                if len == rec.size.0 as u64 * rec.size.1 as u64 {
+                    self.size = rec.size;
+                } else {
+                    self.report(Issue::KeyframeSize {
+                        len,
+                        size: rec.size,
+                    });
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        let len = self.size.0 as u64 * self.size.1 as u64;
+        for pos in positions {
+            if pos >= len {
+                self.report(Issue::OutOfBounds { pos, len });
+            }
+        }
+    }
+
+    fn resize(&mut self, size: (u32, u32)) {
+        if let Err(position::Error::TooLarge { size }) = position::pixel_count(size) {
+            self.report(Issue::CanvasTooLarge { size });
+        }
+        self.size = size;
+    }
+}
+
+fn palette_issue(err: palette::Error) -> Issue {
+    match err {
+        palette::Error::UndefinedColor(col) => Issue::UndefinedColor(col),
+        palette::Error::RemovedColor(col) => Issue::RemovedColor(col),
+        palette::Error::TooLarge { offset, len } => Issue::PaletteTooLarge { offset, len },
+    }
+}
+
+/// Check a complete record stream, returning diagnostics in stream order.
+pub fn validate<'a>(records: impl IntoIterator<Item = &'a CanvasRecord>) -> Vec<Diagnostic> {
+    let mut validator = Validator::new();
+    for record in records {
+        validator.push(record);
+    }
+
+    validator.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use crate::{
+        CanvasKeyframe, CanvasMeta, PaletteInsert, PaletteRemove, PlacementInsert, PlacementRemove,
+    };
+
+    use super::*;
+
+    fn meta() -> CanvasRecord {
+        CanvasRecord::CanvasMeta(CanvasMeta {
+            name: "test".to_string(),
+            platform: "pxls.space".to_string(),
+            time: 0,
+            size: (4, 4),
+        })
+    }
+
+    fn palette() -> CanvasRecord {
+        CanvasRecord::PaletteInsert(PaletteInsert {
+            offset: 0,
+            colors: vec![[0xFF; 4], [0x00, 0x00, 0x00, 0xFF]],
+        })
+    }
+
+    fn place(time: u64, pos: u64, col: u32) -> CanvasRecord {
+        CanvasRecord::PlacementInsert(PlacementInsert { time, pos, col })
+    }
+
+    #[test]
+    fn validate_clean() {
+        let records = [meta(), palette(), place(1, 0, 0), place(2, 15, 1)];
+        assert!(validate(&records).is_empty());
+    }
+
+    #[test]
+    fn validate_issues() {
+        let records = [
+            meta(),
+            palette(),
+            place(10, 16, 0),
+            place(9, 0, 2),
+            CanvasRecord::PaletteRemove(PaletteRemove {
+                offset: 1,
+                length: NonZeroU32::new(1).unwrap(),
+            }),
+            place(11, 0, 1),
+            CanvasRecord::CanvasKeyframe(CanvasKeyframe {
+                time: 12,
+                size: (4, 4),
+                pixels: vec![None; 3],
+            }),
+        ];
+        let issues: Vec<(usize, Issue)> = validate(&records)
+            .into_iter()
+            .map(|d| (d.index, d.issue))
+            .collect();
+
+        assert_eq!(
+            issues,
+            vec![
+                (2, Issue::OutOfBounds { pos: 16, len: 16 }),
+                (
+                    3,
+                    Issue::TimeReversed {
+                        time: 9,
+                        previous: 10
+                    }
+                ),
+                (3, Issue::UndefinedColor(2)),
+                (5, Issue::RemovedColor(1)),
+                (
+                    6,
+                    Issue::KeyframeSize {
+                        len: 3,
+                        size: (4, 4)
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_missing_meta() {
+        // The repeated meta also starts an empty palette
+        let records = [palette(), place(0, 100, 0), meta(), place(1, 0, 0)];
+        let diagnostics = validate(&records);
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    index: 0,
+                    severity: Severity::Error,
+                    issue: Issue::MissingMeta,
+                },
+                Diagnostic {
+                    index: 2,
+                    severity: Severity::Warning,
+                    issue: Issue::RepeatedMeta,
+                },
+                Diagnostic {
+                    index: 3,
+                    severity: Severity::Error,
+                    issue: Issue::UndefinedColor(0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_batch() {
+        let records = [
+            meta(),
+            palette(),
+            CanvasRecord::PlacementRemoveBatch(vec![
+                PlacementRemove { time: 1, pos: 20 },
+                PlacementRemove { time: 2, pos: 0 },
+                PlacementRemove { time: 3, pos: 21 },
+            ]),
+            CanvasRecord::PlacementInsertBatch(Vec::new()),
+        ];
+        let issues: Vec<Issue> = validate(&records).into_iter().map(|d| d.issue).collect();
+
+        assert_eq!(
+            issues,
+            vec![
+                Issue::OutOfBounds { pos: 20, len: 16 },
+                Issue::OutOfBounds { pos: 21, len: 16 },
+                Issue::EmptyBatch,
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_hostile() {
+        let records = [
+            CanvasRecord::CanvasMeta(CanvasMeta {
+                name: "test".to_string(),
+                platform: "pxls.space".to_string(),
+                time: 0,
+                size: (u32::MAX, u32::MAX),
+            }),
+            CanvasRecord::PaletteInsert(PaletteInsert {
+                offset: u32::MAX,
+                colors: vec![[0xFF; 4]],
+            }),
+            palette(),
+            CanvasRecord::PaletteRemove(PaletteRemove {
+                offset: 1,
+                length: NonZeroU32::MAX,
+            }),
+            place(1, u32::MAX as u64 * 2, 0),
+            place(2, u64::MAX, 1),
+        ];
+        let issues: Vec<(usize, Issue)> = validate(&records)
+            .into_iter()
+            .map(|d| (d.index, d.issue))
+            .collect();
+
+        assert_eq!(
+            issues,
+            vec![
+                (
+                    0,
+                    Issue::CanvasTooLarge {
+                        size: (u32::MAX, u32::MAX)
+                    }
+                ),
+                (
+                    1,
+                    Issue::PaletteTooLarge {
+                        offset: u32::MAX,
+                        len: 1
+                    }
+                ),
+                (5, Issue::RemovedColor(1)),
+                (
+                    5,
+                    Issue::OutOfBounds {
+                        pos: u64::MAX,
+                        len: u32::MAX as u64 * u32::MAX as u64
+                    }
+                ),
+            ]
+        );
+    }
+}